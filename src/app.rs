@@ -0,0 +1,339 @@
+#![allow(unused)]
+
+use macroquad::prelude::*;
+use crate::camera::*;
+use crate::consts::*;
+use crate::sim::*;
+use crate::events::SimEvent;
+use crate::snapshot;
+use crate::replay::Command;
+use crate::mixer::Wrench;
+use crate::autopilot::AutopilotMode;
+use crate::util::*;
+use crate::editor::Editor;
+use crate::scene::SceneDef;
+use crate::entity::Entity;
+use crate::mission::Waypoint;
+use crate::controller::{ControllerKind, Pilot};
+use crate::env::TARGET_RADIUS;
+
+/// File used by the quick-save (F5) and quick-load (F9) keys.
+const QUICKSAVE_PATH: &str = "quicksave.ron";
+
+/// Windowed frontend: everything that needs macroquad's window, input or renderer lives here, on top of a headless `Simulation`.
+pub struct App {
+    pub sim: Simulation,
+    pub camera: Camera2D,
+    pub selected_unit: Option<u64>,
+    /// Scene file the world was built from; `None` is the built-in default scene.
+    pub scene_path: Option<String>,
+    pub editor: Option<Editor>,
+    /// Whether distance sensor beams are drawn.
+    pub show_sensors: bool,
+}
+
+impl App {
+
+    pub fn new(sim: Simulation, scene_path: Option<String>) -> Self {
+        Self {
+            sim,
+            camera: create_camera(),
+            selected_unit: None,
+            scene_path,
+            editor: None,
+            show_sensors: true,
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.editor.is_none() {
+            self.sim.advance_with(get_frame_time(), |sim| {
+                for event in sim.step_events() {
+                    if let SimEvent::ReplayFinished { step } = event {
+                        println!("replay finished at step {}", step);
+                    }
+                }
+            });
+        }
+        if let Some(err) = self.sim.recorder.as_mut().and_then(|recorder| recorder.take_error()) {
+            println!("replay recording stopped: {}", err);
+        }
+    }
+
+    /// Sends `command` to the jet with `jet_key` through the simulation, so it is recorded.
+    fn command(&mut self, jet_key: u64, command: Command) {
+        if !self.sim.command(jet_key, command) {
+            println!("command for unknown jet {}", jet_key);
+        }
+    }
+
+    /// Enters the editor with the world reset to its scene, or leaves it and lets the edited world run. A scene the editor
+    /// saved becomes the one it reopens.
+    fn toggle_editor(&mut self) {
+        if let Some(editor) = self.editor.take() {
+            if editor.is_saved() {
+                self.scene_path = Some(editor.path);
+            }
+            return;
+        }
+        if self.sim.recorder.is_some() || self.sim.is_replaying() {
+            println!("editor is unavailable while recording or replaying");
+            return;
+        }
+        let scene = match &self.scene_path {
+            Some(path) => SceneDef::load(path).unwrap_or_else(|err| {
+                println!("{}", err);
+                SceneDef::default_scene()
+            }),
+            None => SceneDef::default_scene(),
+        };
+        self.editor = Some(Editor::new(scene, self.scene_path.clone(), self.sim.get_seed()));
+        self.rebuild_from_editor();
+    }
+
+    fn rebuild_from_editor(&mut self) {
+        let Some(editor) = self.editor.as_ref() else {
+            return;
+        };
+        match Simulation::from_scene(self.sim.get_seed(), &editor.scene) {
+            Ok(sim) => {
+                self.sim = sim;
+                self.selected_unit = None;
+            },
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    pub fn draw(&self) {
+        set_camera(&self.camera);
+        clear_background(BLACK);
+        let alpha = self.sim.alpha();
+        self.draw_boundary();
+        self.draw_entities();
+        self.draw_joints();
+        self.draw_missions();
+        if self.show_sensors {
+            self.draw_sensors();
+        }
+        self.draw_autopilots();
+        if let Some(editor) = self.editor.as_ref() {
+            editor.draw();
+            set_default_camera();
+            editor.draw_hud();
+        }
+    }
+
+    pub fn draw_entities(&self) {
+        let alpha = self.sim.alpha();
+        for entity in self.sim.entities.get_iter() {
+            for shift in self.ghost_offsets(entity.body().pos, entity.get_extent()) {
+                entity.draw_shifted(alpha, shift);
+            }
+        }
+    }
+
+    fn draw_sensors(&self) {
+        let alpha = self.sim.alpha();
+        for jet in self.sim.get_jets() {
+            let (pos, rot) = jet.body().interpolate(alpha);
+            for sensor in jet.sensors.iter() {
+                sensor.draw(pos, rot);
+            }
+            if let Some(estimator) = jet.estimator.as_ref() {
+                estimator.draw();
+            }
+            if let Some(fix) = jet.gps.as_ref().and_then(|gps| gps.get_reading()) {
+                draw_line(fix.pos.x - 4.0, fix.pos.y - 4.0, fix.pos.x + 4.0, fix.pos.y + 4.0, 1.0, SKYBLUE);
+                draw_line(fix.pos.x - 4.0, fix.pos.y + 4.0, fix.pos.x + 4.0, fix.pos.y - 4.0, 1.0, SKYBLUE);
+            }
+        }
+    }
+
+    /// Marks the point each position-holding jet is flying to.
+    fn draw_autopilots(&self) {
+        for jet in self.sim.get_jets() {
+            if let Some(AutopilotMode::Position(target) | AutopilotMode::Pose { target, .. }) = jet.get_autopilot().and_then(|autopilot| autopilot.mode) {
+                draw_circle_lines(target.x, target.y, 6.0, 1.0, GREEN);
+                draw_line(target.x - 9.0, target.y, target.x + 9.0, target.y, 1.0, GREEN);
+                draw_line(target.x, target.y - 9.0, target.x, target.y + 9.0, 1.0, GREEN);
+            }
+        }
+    }
+
+    /// Draws each mission's waypoints with their acceptance radii, the legs already flown dimmed and the active leg from the jet highlighted.
+    fn draw_missions(&self) {
+        for jet in self.sim.get_jets() {
+            if let Pilot::Neural(neural) = &jet.controller {
+                let (target, pos) = (neural.target, jet.body().pos);
+                draw_circle_lines(target.x, target.y, TARGET_RADIUS, 1.0, YELLOW);
+                draw_line(pos.x, pos.y, target.x, target.y, 1.0, ORANGE);
+            }
+            let Some(mission) = jet.mission.as_ref() else {
+                continue;
+            };
+            let waypoints = &mission.mission.waypoints;
+            let active = mission.get_index();
+            for (i, waypoint) in waypoints.iter().enumerate() {
+                let color = if i < active { DARKGRAY } else { YELLOW };
+                draw_circle_lines(waypoint.pos.x, waypoint.pos.y, waypoint.get_radius(), 1.0, color);
+                if let Some(heading) = waypoint.heading {
+                    let tip = waypoint.pos + Vec2::from_angle(heading) * waypoint.get_radius();
+                    draw_line(waypoint.pos.x, waypoint.pos.y, tip.x, tip.y, 1.0, color);
+                }
+                if i > 0 {
+                    let prev = waypoints[i - 1].pos;
+                    draw_line(prev.x, prev.y, waypoint.pos.x, waypoint.pos.y, 1.0, if i <= active { DARKGRAY } else { GOLD });
+                }
+            }
+            if let Some(waypoint) = mission.current() {
+                let pos = jet.body().pos;
+                draw_line(pos.x, pos.y, waypoint.pos.x, waypoint.pos.y, 2.0, ORANGE);
+            }
+        }
+    }
+
+    fn draw_boundary(&self) {
+        for (center, half, rot) in self.sim.get_walls() {
+            let params = DrawRectangleParams { offset: Vec2::new(0.5, 0.5), rotation: rot, color: DARKGRAY };
+            draw_rectangle_ex(center.x, center.y, 2.0 * half.x, 2.0 * half.y, params);
+        }
+        if self.sim.get_boundary() == WorldBoundary::Toroidal {
+            draw_rectangle_lines(-WRAP_MARGIN, -WRAP_MARGIN, WORLD_W + 2.0 * WRAP_MARGIN, WORLD_H + 2.0 * WRAP_MARGIN, 1.0, DARKGRAY);
+        }
+    }
+
+    fn draw_joints(&self) {
+        for handle in self.sim.get_joints() {
+            let Some(joint) = self.sim.physics.impulse_joint_set.get(*handle) else {
+                continue;
+            };
+            let rb1 = self.sim.physics.rigid_bodies.get(joint.body1).unwrap();
+            let rb2 = self.sim.physics.rigid_bodies.get(joint.body2).unwrap();
+            let p1 = rb1.position() * joint.data.local_anchor1();
+            let p2 = rb2.position() * joint.data.local_anchor2();
+            let (c1, c2) = (rb1.center_of_mass(), rb2.center_of_mass());
+            draw_line(c1.x, c1.y, p1.x, p1.y, 1.0, GRAY);
+            draw_line(c2.x, c2.y, p2.x, p2.y, 1.0, GRAY);
+            draw_line(p1.x, p1.y, p2.x, p2.y, 2.0, RED);
+        }
+    }
+
+    /// Where to draw or pick something of radius `extent` at `pos`: itself, plus its ghosts across the seams in toroidal mode.
+    fn ghost_offsets(&self, pos: Vec2, extent: f32) -> Vec<Vec2> {
+        if self.sim.get_boundary() != WorldBoundary::Toroidal {
+            return vec![Vec2::ZERO];
+        }
+        return seam_offsets(pos, extent);
+    }
+
+    pub fn input(&mut self) {
+        control_camera(&mut self.camera);
+        if is_key_pressed(KeyCode::Tab) {
+            self.toggle_editor();
+        }
+        if let Some(editor) = self.editor.as_mut() {
+            let (mouse_posx, mouse_posy) = mouse_position();
+            let mouse = self.camera.screen_to_world(Vec2::new(mouse_posx, mouse_posy));
+            if editor.input(mouse) {
+                self.rebuild_from_editor();
+            }
+            return;
+        }
+        self.mouse_input();
+        self.keyboard();
+    }
+
+    fn keyboard(&mut self) {
+        if is_key_pressed(KeyCode::F5) {
+            match snapshot::save(&self.sim, QUICKSAVE_PATH) {
+                Ok(()) => println!("saved '{}'", QUICKSAVE_PATH),
+                Err(err) => println!("{}", err),
+            }
+        }
+        if is_key_pressed(KeyCode::F9) {
+            match snapshot::load(QUICKSAVE_PATH) {
+                Ok(sim) => {
+                    self.sim = sim;
+                    self.selected_unit = None;
+                    println!("loaded '{}'", QUICKSAVE_PATH);
+                },
+                Err(err) => println!("{}", err),
+            }
+        }
+        if is_key_pressed(KeyCode::L) {
+            self.show_sensors = !self.show_sensors;
+        }
+        if is_key_pressed(KeyCode::B) && self.sim.recorder.is_none() && !self.sim.is_replaying() {
+            let boundary = self.sim.get_boundary().next();
+            self.sim.set_boundary(boundary);
+            println!("boundary: {:?}", boundary);
+        }
+        if self.sim.is_replaying() {
+            return;
+        }
+        if is_key_pressed(KeyCode::Delete) && self.sim.recorder.is_none() {
+            if let Some(key) = self.selected_unit.take() {
+                self.sim.despawn(key);
+            }
+        }
+        let Some(jet) = self.sim.get_jets().next() else {
+            return;
+        };
+        let jet_key = jet.key();
+        if is_key_pressed(KeyCode::C) && jet.mission.is_some() {
+            self.command(jet_key, Command::Abort);
+            return;
+        }
+        if is_key_pressed(KeyCode::K) {
+            let kind = jet.controller.kind().unwrap_or(ControllerKind::Keyboard).next();
+            println!("controller: {}", kind.name());
+            self.command(jet_key, Command::Control(kind));
+            return;
+        }
+        if is_key_pressed(KeyCode::P) {
+            if let Some(autopilot) = jet.get_autopilot() {
+                let command = match autopilot.is_engaged() {
+                    true => Command::Disengage,
+                    false => Command::Engage(AutopilotMode::Position(jet.body().pos)),
+                };
+                self.command(jet_key, command);
+            }
+            return;
+        }
+        let stick = Wrench::new(key_axis(KeyCode::S, KeyCode::W), key_axis(KeyCode::Q, KeyCode::E), key_axis(KeyCode::A, KeyCode::D));
+        if jet.get_stick().is_some_and(|current| current != stick) {
+            self.command(jet_key, Command::Stick(stick));
+        }
+    }
+
+    fn mouse_input(&mut self) {
+        if is_mouse_button_released(MouseButton::Left) {
+            self.selected_unit = None;
+            let (mouse_posx, mouse_posy) = mouse_position();
+            let mouse_pos = Vec2::new(mouse_posx, mouse_posy);
+            let rel_coords = self.camera.screen_to_world(mouse_pos);
+            for entity in self.sim.entities.get_iter() {
+                let pos = entity.body().pos;
+                let hit = self.ghost_offsets(pos, entity.get_extent()).into_iter().any(|shift| contact_mouse(rel_coords, pos + shift, entity.get_extent()));
+                if hit {
+                    self.selected_unit = Some(entity.key());
+                    break;
+                }
+            }
+        }
+        if is_mouse_button_released(MouseButton::Right) && !self.sim.is_replaying() {
+            let (mouse_posx, mouse_posy) = mouse_position();
+            let target = self.camera.screen_to_world(Vec2::new(mouse_posx, mouse_posy));
+            let jet_key = self.sim.get_jets().next().map(|jet| jet.key());
+            if let Some(jet_key) = jet_key {
+                self.command(jet_key, Command::Waypoint(Waypoint::at(target)));
+            }
+        }
+    }
+
+}
+
+/// -1 while `negative` is held, 1 while `positive` is, 0 for neither or both.
+fn key_axis(negative: KeyCode, positive: KeyCode) -> f32 {
+    return is_key_down(positive) as i32 as f32 - is_key_down(negative) as i32 as f32;
+}
@@ -0,0 +1,258 @@
+#![allow(unused)]
+
+use std::fmt;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::mixer::Wrench;
+use crate::physics::PhysicsData;
+use crate::util::angle_diff;
+
+/// PID gains; `i_limit` caps how much of the output the integral term may contribute.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub i_limit: f32,
+}
+
+impl Gains {
+
+    pub fn new(kp: f32, ki: f32, kd: f32, i_limit: f32) -> Self {
+        Self { kp, ki, kd, i_limit }
+    }
+}
+
+/// State of one PID loop. Output is clamped to -1..1 and the integral only grows while the output isn't
+/// saturated in the same direction, so it can't wind up.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Pid {
+    integral: f32,
+    prev_error: Option<f32>,
+}
+
+impl Pid {
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.prev_error = None;
+    }
+
+    /// Derivative taken from the change in error since the last call.
+    pub fn update(&mut self, gains: &Gains, error: f32, dt: f32) -> f32 {
+        let rate = match self.prev_error {
+            Some(prev) => (error - prev) / dt,
+            None => 0.0,
+        };
+        return self.update_with_rate(gains, error, rate, dt);
+    }
+
+    /// `rate` is the error's time derivative, usually minus a measured rate so setpoint jumps don't kick the output.
+    pub fn update_with_rate(&mut self, gains: &Gains, error: f32, rate: f32, dt: f32) -> f32 {
+        self.prev_error = Some(error);
+        let unclamped = gains.kp * error + gains.ki * self.integral + gains.kd * rate;
+        let output = unclamped.clamp(-1.0, 1.0);
+        let saturated = output != unclamped && output.signum() == error.signum();
+        if !saturated && gains.ki != 0.0 {
+            let limit = gains.i_limit / gains.ki.abs();
+            self.integral = (self.integral + error * dt).clamp(-limit, limit);
+        }
+        return output;
+    }
+}
+
+/// What the autopilot is holding.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AutopilotMode {
+    /// Keep pointing at `heading` radians; forward and lateral stay with the pilot.
+    Heading(f32),
+    /// Fly at `speed` px/s along `heading`.
+    Speed { speed: f32, heading: f32 },
+    /// Hover over a point.
+    Position(Vec2),
+    /// Hover over a point facing `heading`.
+    Pose { target: Vec2, heading: f32 },
+}
+
+impl fmt::Display for AutopilotMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutopilotMode::Heading(heading) => write!(f, "heading {}", heading),
+            AutopilotMode::Speed { speed, heading } => write!(f, "speed {} {}", speed, heading),
+            AutopilotMode::Position(target) => write!(f, "hold {} {}", target.x, target.y),
+            AutopilotMode::Pose { target, heading } => write!(f, "pose {} {} {}", target.x, target.y, heading),
+        }
+    }
+}
+
+impl AutopilotMode {
+
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let mut values = vec![];
+        for word in words.iter().skip(1) {
+            values.push(word.parse::<f32>().map_err(|_| format!("bad autopilot value '{}'", word))?);
+        }
+        match (words.first(), values.as_slice()) {
+            (Some(&"heading"), [heading]) => Ok(AutopilotMode::Heading(*heading)),
+            (Some(&"speed"), [speed, heading]) => Ok(AutopilotMode::Speed { speed: *speed, heading: *heading }),
+            (Some(&"hold"), [x, y]) => Ok(AutopilotMode::Position(Vec2::new(*x, *y))),
+            (Some(&"pose"), [x, y, heading]) => Ok(AutopilotMode::Pose { target: Vec2::new(*x, *y), heading: *heading }),
+            _ => Err(format!("unknown autopilot mode '{}'", words.join(" "))),
+        }
+    }
+}
+
+/// Loop gains, tunable per jet from the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AutopilotGains {
+    /// Heading error in radians to turn throttle, damped by the measured yaw rate.
+    pub heading: Gains,
+    /// Body-frame velocity error in px/s to forward and lateral throttle.
+    pub velocity: Gains,
+    /// Position error in px to a velocity setpoint, as a fraction of `max_speed`.
+    pub position: Gains,
+    /// Fastest the position loop will ask the jet to fly, in px/s.
+    pub max_speed: f32,
+}
+
+impl Default for AutopilotGains {
+    fn default() -> Self {
+        Self {
+            heading: Gains::new(4.0, 0.0, 1.0, 0.0),
+            velocity: Gains::new(0.05, 0.02, 0.0, 0.3),
+            position: Gains::new(0.005, 0.0, 0.0, 0.0),
+            max_speed: 80.0,
+        }
+    }
+}
+
+/// Velocity errors below this, in px/s, no longer turn the jet; it just holds its last heading.
+const STEER_THRESHOLD: f32 = 10.0;
+
+/// Cascaded PID autopilot: position feeds a velocity setpoint, velocity is held in the body frame, and heading
+/// is held separately. Its output is a throttle per axis, the same thing the stick produces.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Autopilot {
+    pub mode: Option<AutopilotMode>,
+    pub gains: AutopilotGains,
+    /// Lower cap on `gains.max_speed` for the position loop, e.g. set per mission leg.
+    #[serde(default)]
+    pub speed_limit: Option<f32>,
+    heading_setpoint: f32,
+    heading: Pid,
+    forward: Pid,
+    lateral: Pid,
+    position_x: Pid,
+    position_y: Pid,
+}
+
+impl Autopilot {
+
+    pub fn with_gains(gains: AutopilotGains) -> Self {
+        return Self { gains, ..Default::default() };
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        return self.mode.is_some();
+    }
+
+    /// Takes over from the pilot; `heading` is the jet's current heading, held until the mode says otherwise.
+    pub fn engage(&mut self, mode: AutopilotMode, heading: f32) {
+        for pid in [&mut self.heading, &mut self.forward, &mut self.lateral, &mut self.position_x, &mut self.position_y] {
+            pid.reset();
+        }
+        self.heading_setpoint = heading;
+        self.mode = Some(mode);
+    }
+
+    /// Switches to `mode`, keeping the loops' state if already engaged so setpoint changes don't jolt the jet.
+    pub fn steer(&mut self, mode: AutopilotMode, heading: f32) {
+        match self.mode {
+            Some(_) => self.mode = Some(mode),
+            None => self.engage(mode, heading),
+        }
+    }
+
+    pub fn disengage(&mut self) {
+        self.mode = None;
+        self.speed_limit = None;
+    }
+
+    /// Throttle for this step from the jet's physics state, or `None` when disengaged. `manual` is the pilot's
+    /// throttle, passed through on axes the mode doesn't control.
+    pub fn update(&mut self, state: &PhysicsData, manual: Wrench, dt: f32) -> Option<Wrench> {
+        let mode = self.mode?;
+        let gains = self.gains;
+        let velocity_setpoint = match mode {
+            AutopilotMode::Heading(heading) => {
+                self.heading_setpoint = heading;
+                None
+            },
+            AutopilotMode::Speed { speed, heading } => {
+                self.heading_setpoint = heading;
+                Some(Vec2::from_angle(heading) * speed)
+            },
+            AutopilotMode::Position(target) => {
+                let setpoint = self.position_setpoint(target, state, dt);
+                // Most jets only push along their nose, so point it where the velocity needs to change.
+                let correction = setpoint - state.velocity;
+                if correction.length() > STEER_THRESHOLD {
+                    self.heading_setpoint = correction.y.atan2(correction.x);
+                }
+                Some(setpoint)
+            },
+            AutopilotMode::Pose { target, heading } => {
+                self.heading_setpoint = heading;
+                Some(self.position_setpoint(target, state, dt))
+            },
+        };
+        let heading_error = angle_diff(state.rotation, self.heading_setpoint);
+        let torque = self.heading.update_with_rate(&gains.heading, heading_error, -state.spin, dt);
+        let Some(setpoint) = velocity_setpoint else {
+            return Some(Wrench::new(manual.forward, manual.lateral, torque));
+        };
+        let error = setpoint - state.velocity;
+        let nose = Vec2::from_angle(state.rotation);
+        let forward = self.forward.update(&gains.velocity, error.dot(nose), dt);
+        let lateral = self.lateral.update(&gains.velocity, error.dot(nose.perp()), dt);
+        return Some(Wrench::new(forward, lateral, torque));
+    }
+
+    fn position_setpoint(&mut self, target: Vec2, state: &PhysicsData, dt: f32) -> Vec2 {
+        let error = target - state.position;
+        let x = self.position_x.update(&self.gains.position, error.x, dt);
+        let y = self.position_y.update(&self.gains.position, error.y, dt);
+        let max_speed = self.speed_limit.map(|limit| limit.min(self.gains.max_speed)).unwrap_or(self.gains.max_speed);
+        return Vec2::new(x, y) * max_speed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integral_does_not_wind_up_while_saturated() {
+        // `i_limit` is far above the output range, so only anti-windup can stop the integral.
+        let gains = Gains::new(1.0, 1.0, 0.0, 100.0);
+        let mut pid = Pid::default();
+        for _ in 0..300 {
+            assert_eq!(pid.update(&gains, 5.0, 0.1), 1.0);
+        }
+        assert_eq!(pid.integral, 0.0);
+        // Once the error reverses the output follows straight away instead of first unwinding 30 s of integral.
+        assert!(pid.update(&gains, -0.5, 0.1) < 0.0);
+    }
+
+    #[test]
+    fn integral_grows_while_unsaturated() {
+        let gains = Gains::new(1.0, 1.0, 0.0, 100.0);
+        let mut pid = Pid::default();
+        let first = pid.update(&gains, 0.2, 0.1);
+        for _ in 0..10 {
+            pid.update(&gains, 0.2, 0.1);
+        }
+        assert!((pid.integral - 0.22).abs() < 1e-5);
+        assert!(pid.update(&gains, 0.2, 0.1) > first);
+    }
+}
@@ -20,10 +20,8 @@ pub fn control_camera(camera: &mut Camera2D /* , screen_ratio: f32 */) {
     if is_key_pressed(KeyCode::KpAdd) {
         camera.zoom += Vec2::new(ZOOM_RATE * 0.1, -SCREEN_RATIO * ZOOM_RATE * 0.1);
     }
-    if is_key_pressed(KeyCode::KpSubtract) {
-        if camera.zoom.x > 0.0001 {
-            camera.zoom -= Vec2::new(ZOOM_RATE * 0.1, -SCREEN_RATIO * ZOOM_RATE * 0.1);
-        }
+    if is_key_pressed(KeyCode::KpSubtract) && camera.zoom.x > 0.0001 {
+        camera.zoom -= Vec2::new(ZOOM_RATE * 0.1, -SCREEN_RATIO * ZOOM_RATE * 0.1);
     }
     if is_key_pressed(KeyCode::KpMultiply) {
         camera.zoom = Vec2::new(ZOOM_RATE, -SCREEN_RATIO * ZOOM_RATE);
@@ -1,81 +1,113 @@
 #![allow(unused)]
 
 
-use std::collections::hash_map::{Iter, IterMut};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::f32::consts::PI;
 use crate::consts::{WORLD_H, WORLD_W};
 //use crate::sim::*;
 use crate::util::*;
 use crate::physics::*;
 use crate::unit::*;
+use crate::jet::Jet;
+use crate::entity::*;
+use crate::rng::SimRng;
 use macroquad::{color, prelude::*};
-use macroquad::rand::*;
 use rapier2d::geometry::*;
 use rapier2d::na::Vector2;
 use rapier2d::prelude::{RigidBody, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
 
 
 
+/// Single registry of every entity in the world, keyed by entity key.
+#[derive(Serialize, Deserialize)]
 pub struct Collector {
-    pub units: HashMap<u64, Unit>,
+    pub objects: BTreeMap<u64, Object>,
 }
 
 impl Collector {
     pub fn new() -> Self {
         Self {
-            units: HashMap::new(),
+            objects: BTreeMap::new(),
         }
     }
 
-    pub fn add_many_units(&mut self, units_num: usize, physics: &mut Physics) {
+    pub fn add_many_units(&mut self, units_num: usize, physics: &mut Physics, rng: &mut SimRng) {
         for _ in 0..units_num {
-            let unit = Unit::new(physics);
-            _ = self.add_unit(unit, physics);
+            let unit = Unit::new(physics, rng);
+            _ = self.add(unit);
         }
     }
 
-    pub fn add_complex(&mut self, physics: &mut Physics) -> u64 {
+    /// Random-colored five-part unit: a ball hub with two struts and two fins, placed anywhere in the world.
+    pub fn add_complex(&mut self, physics: &mut Physics, rng: &mut SimRng) -> u64 {
+        let fin = PartShape::Convex { points: vec![Vec2::new(-6.0, -6.0), Vec2::new(8.0, 0.0), Vec2::new(-6.0, 6.0)] };
+        let strut = PartShape::Cuboid { half_extents: Vec2::new(10.0, 3.0) };
         let parts = PartsScheme {
             parts: vec![
-                PartDef {shape_size: 10.0, relative_position: Vec2::ZERO},
-                PartDef {shape_size: 6.0, relative_position: Vec2::new(-20.0, -20.0)},
-                PartDef {shape_size: 6.0, relative_position: Vec2::new(-20.0, 20.0)},
-                PartDef {shape_size: 6.0, relative_position: Vec2::new(20.0, -20.0)},
-                PartDef {shape_size: 6.0, relative_position: Vec2::new(20.0, 20.0)},
+                PartDef::new(PartShape::Ball { radius: 10.0 }, Vec2::ZERO).colored(RED),
+                PartDef::new(strut.clone(), Vec2::new(-20.0, -20.0)).rotated(PI / 4.0),
+                PartDef::new(strut, Vec2::new(20.0, 20.0)).rotated(PI / 4.0),
+                PartDef::new(fin.clone(), Vec2::new(-20.0, 20.0)).rotated(3.0 * PI / 4.0).with_material(PhysicsProperities::bounce()),
+                PartDef::new(fin, Vec2::new(20.0, -20.0)).rotated(-PI / 4.0).with_material(PhysicsProperities::bounce()),
             ]
         };
-        let unit = Unit::new(physics);
-        let key = unit.key;
-        self.units.insert(key, unit);
-        return key;
+        let color = random_color(rng);
+        let pos = random_position(WORLD_W, WORLD_H, rng);
+        let unit = Unit::from_scheme(&parts, pos, 0.0, color, PhysicsProperities::default(), physics, rng);
+        return self.add(unit);
     }
 
-    pub fn add_unit(&mut self, mut unit: Unit, physics: &mut Physics) -> u64 {
-        let key = unit.key;
-        //let handle = physics.add_dynamic(key, &unit.pos, unit.rot, unit.shape.clone(), PhysicsProperities::default());
-        //unit.physics_handle = Some(handle);
-        self.units.insert(key, unit);
+    /// Registers an entity whose rigid body already exists.
+    pub fn add(&mut self, object: impl Into<Object>) -> u64 {
+        let object = object.into();
+        let key = object.entity().key();
+        self.objects.insert(key, object);
         return key;
     }
 
-    pub fn get(&self, id: u64) -> Option<&Unit> {
-        return self.units.get(&id);
+    pub fn get(&self, id: u64) -> Option<&dyn Entity> {
+        return self.objects.get(&id).map(|object| object.entity());
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut dyn Entity> {
+        return self.objects.get_mut(&id).map(|object| object.entity_mut());
+    }
+
+    pub fn get_object(&self, id: u64) -> Option<&Object> {
+        return self.objects.get(&id);
+    }
+
+    pub fn get_object_mut(&mut self, id: u64) -> Option<&mut Object> {
+        return self.objects.get_mut(&id);
     }
 
-    pub fn _remove(&mut self, id: u64) {
-        self.units.remove(&id);
+    /// Takes the entity out of the registry only; its physics body is the caller's to remove.
+    pub fn remove(&mut self, id: u64) -> Option<Object> {
+        return self.objects.remove(&id);
     }
 
-    pub fn get_iter(&self) -> Iter<u64, Unit> {
-        return self.units.iter();
+    pub fn get_iter(&self) -> impl Iterator<Item = &dyn Entity> {
+        return self.objects.values().map(|object| object.entity());
     }
 
-    pub fn get_iter_mut(&mut self) -> IterMut<u64, Unit> {
-        return self.units.iter_mut();
+    pub fn get_iter_mut(&mut self) -> impl Iterator<Item = &mut dyn Entity> {
+        return self.objects.values_mut().map(|object| object.entity_mut());
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = &Unit> {
+        return self.objects.values().filter_map(|object| object.as_unit());
+    }
+
+    pub fn jets(&self) -> impl Iterator<Item = &Jet> {
+        return self.objects.values().filter_map(|object| object.as_jet());
+    }
+
+    pub fn jets_mut(&mut self) -> impl Iterator<Item = &mut Jet> {
+        return self.objects.values_mut().filter_map(|object| object.as_jet_mut());
     }
 
     pub fn _count(&self) -> usize {
-        return self.units.len();
+        return self.objects.len();
     }
-}
\ No newline at end of file
+}
@@ -8,9 +8,25 @@ pub const SCREEN_WIDTH: f32 = 1200.0;
 pub const SCREEN_HEIGHT: f32 = 900.0;
 pub const WORLD_W: f32 = 1200.0;
 pub const WORLD_H: f32 = 900.0;
+pub const WRAP_MARGIN: f32 = 5.0;
+pub const WALL_THICKNESS: f32 = 20.0;
 
 pub const SIZE_MIN: u32 = 5;
 pub const SIZE_MAX: u32 = 14;
 
 pub const SPEED: i32 = 3000;
-pub const ROTATE: f32 = 1.0;
\ No newline at end of file
+pub const ROTATE: f32 = 1.0;
+/// Pixels of exhaust plume drawn per unit of engine thrust.
+pub const EXHAUST_SCALE: f32 = 0.01;
+/// Length of the nozzle stub drawn at each engine.
+pub const NOZZLE_LENGTH: f32 = 5.0;
+
+/// How fast a jet's throttle follows the stick, in full scale per second.
+pub const THROTTLE_RATE: f32 = 2.0;
+/// Default engine spool time constants, in seconds.
+pub const SPOOL_UP: f32 = 0.25;
+pub const SPOOL_DOWN: f32 = 0.4;
+/// Wall-clock time a jet controller gets per physics tick.
+pub const CONTROL_BUDGET: std::time::Duration = std::time::Duration::from_millis(2);
+/// Contact forces weaker than this (summed over a collider pair) raise no `ContactForce` event, so grazing and resting touches stay quiet.
+pub const CONTACT_FORCE_THRESHOLD: f32 = 1.0;
@@ -0,0 +1,348 @@
+#![allow(unused)]
+
+use std::fmt;
+use std::time::Duration;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::autopilot::*;
+use crate::consts::*;
+use crate::env;
+use crate::estimator::Estimate;
+use crate::jet::JetEng;
+use crate::mixer::{EngineSetting, Wrench};
+use crate::neural::Network;
+use crate::physics::PhysicsData;
+use crate::replay::Command;
+use crate::sensors::*;
+
+/// Everything a controller may look at on one tick.
+pub struct Observation<'a> {
+    /// Ground truth from physics; realistic controllers should prefer `estimate` and the sensors.
+    pub state: PhysicsData,
+    pub estimate: Option<Estimate>,
+    pub ranges: &'a [RangeSensor],
+    pub imu: Option<ImuReading>,
+    pub gps: Option<GpsReading>,
+    /// Where the jet's throttle settles when nothing asks for more.
+    pub neutral: Wrench,
+    /// Engine layout and current state, for controllers that set engines directly.
+    pub engines: &'a [JetEng],
+    /// Wall-clock time the controller should stay within.
+    pub budget: Duration,
+}
+
+/// What a controller asks of the engines.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Actuation {
+    /// Throttle per axis, each in -1..1, handed to the mixer.
+    Throttle(Wrench),
+    /// Throttle and nozzle angle per engine, bypassing the mixer.
+    Engines(Vec<EngineSetting>),
+}
+
+impl Default for Actuation {
+    fn default() -> Self {
+        return Actuation::Throttle(Wrench::default());
+    }
+}
+
+/// Flight logic run once per physics tick. A new kind needs a variant in `Pilot` and an impl of this trait.
+pub trait Controller {
+
+    fn name(&self) -> &'static str;
+
+    /// Engine commands for this tick.
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation;
+
+    /// Takes a pilot command meant for this controller; returns false if it has no use for it. Autopilot and mission
+    /// commands are handled by the jet.
+    fn command(&mut self, command: Command) -> bool {
+        return false;
+    }
+
+    fn autopilot(&self) -> Option<&Autopilot> {
+        return None;
+    }
+
+    fn autopilot_mut(&mut self) -> Option<&mut Autopilot> {
+        return None;
+    }
+
+    /// Stick position, for controllers flown by hand.
+    fn stick(&self) -> Option<Wrench> {
+        return None;
+    }
+}
+
+/// Flown by hand: the throttle ramps towards the stick at `THROTTLE_RATE`, or towards `neutral` on centred axes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Keyboard {
+    /// Stick position: forward, lateral and turn, each in -1..1.
+    pub stick: Wrench,
+    pub throttle: Wrench,
+}
+
+impl Keyboard {
+
+    /// Holds the stick; each axis is clamped to -1..1.
+    pub fn set_stick(&mut self, stick: Wrench) {
+        self.stick = Wrench::new(stick.forward.clamp(-1.0, 1.0), stick.lateral.clamp(-1.0, 1.0), stick.torque.clamp(-1.0, 1.0));
+    }
+}
+
+impl Controller for Keyboard {
+
+    fn name(&self) -> &'static str {
+        return "keyboard";
+    }
+
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation {
+        let step = THROTTLE_RATE*dt;
+        let ramp = |current: f32, stick: f32, neutral: f32| {
+            let target = if stick != 0.0 { stick } else { neutral };
+            return current + (target - current).clamp(-step, step);
+        };
+        let neutral = observation.neutral;
+        self.throttle = Wrench::new(
+            ramp(self.throttle.forward, self.stick.forward, neutral.forward),
+            ramp(self.throttle.lateral, self.stick.lateral, neutral.lateral),
+            ramp(self.throttle.torque, self.stick.torque, neutral.torque),
+        );
+        return Actuation::Throttle(self.throttle);
+    }
+
+    fn command(&mut self, command: Command) -> bool {
+        match command {
+            Command::Go(thrust) => self.set_stick(Wrench::new(thrust, 0.0, 0.0)),
+            // Full torque counter-clockwise on screen (y points down, so that is a negative angle).
+            Command::TurnLeft => self.set_stick(Wrench::new(0.0, 0.0, -1.0)),
+            Command::TurnRight => self.set_stick(Wrench::new(0.0, 0.0, 1.0)),
+            Command::Stick(stick) => self.set_stick(stick),
+            _ => return false,
+        }
+        return true;
+    }
+
+    fn stick(&self) -> Option<Wrench> {
+        return Some(self.stick);
+    }
+}
+
+/// Flown by hand until the autopilot is engaged; it then overrides the axes its mode controls.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AutopilotControl {
+    pub manual: Keyboard,
+    pub autopilot: Autopilot,
+}
+
+impl AutopilotControl {
+
+    pub fn with_gains(gains: AutopilotGains) -> Self {
+        return Self { manual: Keyboard::default(), autopilot: Autopilot::with_gains(gains) };
+    }
+}
+
+impl Controller for AutopilotControl {
+
+    fn name(&self) -> &'static str {
+        return "autopilot";
+    }
+
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation {
+        let Actuation::Throttle(manual) = self.manual.control(observation, dt) else {
+            unreachable!("keyboard control is always a throttle");
+        };
+        let throttle = self.autopilot.update(&observation.state, manual, dt).unwrap_or(manual);
+        return Actuation::Throttle(throttle);
+    }
+
+    fn command(&mut self, command: Command) -> bool {
+        return self.manual.command(command);
+    }
+
+    fn autopilot(&self) -> Option<&Autopilot> {
+        return Some(&self.autopilot);
+    }
+
+    fn autopilot_mut(&mut self) -> Option<&mut Autopilot> {
+        return Some(&mut self.autopilot);
+    }
+
+    fn stick(&self) -> Option<Wrench> {
+        return self.manual.stick();
+    }
+}
+
+/// One step of a throttle script: from `time` seconds on, hold `throttle`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScriptKey {
+    pub time: f32,
+    pub throttle: Wrench,
+}
+
+/// Plays back a fixed throttle timeline; with `repeat` it starts over on reaching the last key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Scripted {
+    pub keys: Vec<ScriptKey>,
+    #[serde(default)]
+    pub repeat: bool,
+    #[serde(default)]
+    time: f32,
+}
+
+impl Scripted {
+
+    /// `keys` are sorted by time.
+    pub fn new(mut keys: Vec<ScriptKey>, repeat: bool) -> Self {
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        return Self { keys, repeat, time: 0.0 };
+    }
+}
+
+impl Controller for Scripted {
+
+    fn name(&self) -> &'static str {
+        return "scripted";
+    }
+
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation {
+        let length = self.keys.last().map(|key| key.time).unwrap_or(0.0);
+        if self.repeat && length > 0.0 && self.time >= length {
+            self.time -= length;
+        }
+        let throttle = self.keys.iter().take_while(|key| key.time <= self.time).last().map(|key| key.throttle).unwrap_or(observation.neutral);
+        self.time += dt;
+        return Actuation::Throttle(throttle);
+    }
+}
+
+/// Driven from outside the simulation, e.g. a training loop: holds whatever action it was last given.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct External {
+    pub action: Actuation,
+}
+
+impl Controller for External {
+
+    fn name(&self) -> &'static str {
+        return "external";
+    }
+
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation {
+        return self.action.clone();
+    }
+}
+
+/// Trained network flying towards `target`: it sees what a `JetEnv` agent sees and sets the engines the way its
+/// actions do, so a genome flies here as it did in training.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Neural {
+    pub network: Network,
+    pub target: Vec2,
+}
+
+impl Controller for Neural {
+
+    fn name(&self) -> &'static str {
+        return "neural";
+    }
+
+    fn control(&mut self, observation: &Observation, dt: f32) -> Actuation {
+        let input = env::observe(&observation.state, self.target, observation.ranges);
+        let action = env::action_space(observation.engines).scale(&self.network.forward(&input));
+        return Actuation::Engines(env::engine_settings(observation.engines, &action));
+    }
+}
+
+/// Which controllers `Command::Control` can switch a jet to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControllerKind {
+    Keyboard,
+    Autopilot,
+    External,
+}
+
+impl ControllerKind {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "keyboard" => Some(ControllerKind::Keyboard),
+            "autopilot" => Some(ControllerKind::Autopilot),
+            "external" => Some(ControllerKind::External),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ControllerKind::Keyboard => "keyboard",
+            ControllerKind::Autopilot => "autopilot",
+            ControllerKind::External => "external",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            ControllerKind::Keyboard => ControllerKind::Autopilot,
+            ControllerKind::Autopilot => ControllerKind::External,
+            ControllerKind::External => ControllerKind::Keyboard,
+        }
+    }
+}
+
+/// Controller slot on a jet, holding any controller kind.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Pilot {
+    Keyboard(Keyboard),
+    Autopilot(AutopilotControl),
+    Scripted(Scripted),
+    External(External),
+    Neural(Neural),
+}
+
+impl Default for Pilot {
+    fn default() -> Self {
+        return Pilot::Autopilot(AutopilotControl::default());
+    }
+}
+
+impl Pilot {
+
+    pub fn controller(&self) -> &dyn Controller {
+        match self {
+            Pilot::Keyboard(keyboard) => keyboard,
+            Pilot::Autopilot(autopilot) => autopilot,
+            Pilot::Scripted(scripted) => scripted,
+            Pilot::External(external) => external,
+            Pilot::Neural(neural) => neural,
+        }
+    }
+
+    pub fn controller_mut(&mut self) -> &mut dyn Controller {
+        match self {
+            Pilot::Keyboard(keyboard) => keyboard,
+            Pilot::Autopilot(autopilot) => autopilot,
+            Pilot::Scripted(scripted) => scripted,
+            Pilot::External(external) => external,
+            Pilot::Neural(neural) => neural,
+        }
+    }
+
+    /// A fresh controller of `kind`; an autopilot gets `gains`.
+    pub fn of_kind(kind: ControllerKind, gains: AutopilotGains) -> Self {
+        match kind {
+            ControllerKind::Keyboard => Pilot::Keyboard(Keyboard::default()),
+            ControllerKind::Autopilot => Pilot::Autopilot(AutopilotControl::with_gains(gains)),
+            ControllerKind::External => Pilot::External(External::default()),
+        }
+    }
+
+    pub fn kind(&self) -> Option<ControllerKind> {
+        match self {
+            Pilot::Keyboard(_) => Some(ControllerKind::Keyboard),
+            Pilot::Autopilot(_) => Some(ControllerKind::Autopilot),
+            Pilot::Scripted(_) | Pilot::Neural(_) => None,
+            Pilot::External(_) => Some(ControllerKind::External),
+        }
+    }
+}
@@ -0,0 +1,446 @@
+#![allow(unused)]
+
+use std::f32::consts::PI;
+use macroquad::prelude::*;
+use crate::consts::{SIZE_MAX, SIZE_MIN, WORLD_H, WORLD_W};
+use crate::physics::*;
+use crate::rng::SimRng;
+use crate::scene::*;
+use crate::sim::*;
+use crate::util::*;
+
+/// Where the editor saves when the scene didn't come from a file.
+const EDITED_SCENE_PATH: &str = "scenes/edited.ron";
+const ROTATE_STEP: f32 = PI / 12.0;
+const SCALE_STEP: f32 = 1.1;
+const PROPERTY_STEP: f32 = 0.05;
+/// Size of newly placed units, and the size assumed for units that leave theirs to the random generator.
+const SIZE_DEFAULT: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Select,
+    Unit,
+    Wall,
+    Jet,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Select => "select",
+            Tool::Unit => "place unit",
+            Tool::Wall => "place wall",
+            Tool::Jet => "place jet",
+        }
+    }
+}
+
+/// Index of an object in the edited `SceneDef`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Wall(usize),
+    Unit(usize),
+    Jet(usize),
+}
+
+/// Mouse-driven scene editor. It edits a `SceneDef` and the frontend rebuilds the world from it after every change,
+/// so the editor and the simulation always build objects through the same spawn functions.
+pub struct Editor {
+    pub scene: SceneDef,
+    pub path: String,
+    tool: Tool,
+    selected: Option<Selection>,
+    drag_offset: Option<Vec2>,
+    status: String,
+    /// Whether the scene has been saved to `path` since the editor opened.
+    saved: bool,
+}
+
+impl Editor {
+
+    /// Opens `scene` for editing. Units left to the random generator, or spawned with a `count`, are first expanded into
+    /// one entry each with the placement, size and color drawn from `seed`, so every unit can be selected.
+    pub fn new(mut scene: SceneDef, path: Option<String>, seed: u64) -> Self {
+        Self::expand_units(&mut scene, seed);
+        Self {
+            scene,
+            path: path.unwrap_or_else(|| EDITED_SCENE_PATH.to_string()),
+            tool: Tool::Select,
+            selected: None,
+            drag_offset: None,
+            status: String::new(),
+            saved: false,
+        }
+    }
+
+    pub fn is_saved(&self) -> bool {
+        return self.saved;
+    }
+
+    fn expand_units(scene: &mut SceneDef, seed: u64) {
+        let mut rng = SimRng::new(seed);
+        let mut units = vec![];
+        for def in scene.units.drain(..) {
+            for _ in 0..def.count.unwrap_or(1) {
+                let mut unit = def.clone();
+                unit.count = None;
+                unit.pos = Some(def.pos.unwrap_or_else(|| random_position(WORLD_W, WORLD_H, &mut rng)));
+                if unit.parts.is_empty() {
+                    unit.size = Some(def.size.unwrap_or_else(|| rng.gen_range_u32(SIZE_MIN, SIZE_MAX) as f32));
+                }
+                let color = def.color.map(Color::from).unwrap_or_else(|| random_color(&mut rng));
+                unit.color = Some([color.r, color.g, color.b, color.a]);
+                units.push(unit);
+            }
+        }
+        scene.units = units;
+    }
+
+    /// Radius around a unit's position that selects it.
+    fn unit_radius(unit: &UnitDef) -> f32 {
+        if unit.parts.is_empty() {
+            return 3.0 * unit.size.unwrap_or(SIZE_DEFAULT);
+        }
+        return unit.parts.iter().fold(0.0, |r, part| r.max(part.offset.length() + part.shape.get_radius()));
+    }
+
+    /// Handles one frame of editor input at world position `mouse`. Returns true when the scene changed.
+    pub fn input(&mut self, mouse: Vec2) -> bool {
+        let mut changed = false;
+        self.tool_keys();
+        changed |= self.mouse_input(mouse);
+        if let Some(selection) = self.selected {
+            changed |= self.edit_keys(selection);
+        }
+        if is_key_down(KeyCode::LeftControl) && is_key_pressed(KeyCode::S) {
+            self.status = match self.scene.save(&self.path) {
+                Ok(()) => {
+                    self.saved = true;
+                    format!("saved '{}'", self.path)
+                },
+                Err(err) => err.to_string(),
+            };
+        }
+        return changed;
+    }
+
+    fn tool_keys(&mut self) {
+        let tools = [(KeyCode::Key1, Tool::Select), (KeyCode::Key2, Tool::Unit), (KeyCode::Key3, Tool::Wall), (KeyCode::Key4, Tool::Jet)];
+        for (key, tool) in tools {
+            if is_key_pressed(key) {
+                self.tool = tool;
+            }
+        }
+    }
+
+    fn mouse_input(&mut self, mouse: Vec2) -> bool {
+        if is_mouse_button_pressed(MouseButton::Left) {
+            self.selected = self.pick(mouse);
+            if self.selected.is_none() && self.tool != Tool::Select {
+                self.selected = Some(self.place(mouse));
+                self.drag_offset = Some(Vec2::ZERO);
+                return true;
+            }
+            self.drag_offset = self.selected.map(|selection| self.get_pos(selection) - mouse);
+        }
+        if is_mouse_button_released(MouseButton::Left) {
+            self.drag_offset = None;
+        }
+        if let (Some(selection), Some(offset)) = (self.selected, self.drag_offset) {
+            if is_mouse_button_down(MouseButton::Left) && self.get_pos(selection) != mouse + offset {
+                self.set_pos(selection, mouse + offset);
+                return true;
+            }
+        }
+        return false;
+    }
+
+    fn edit_keys(&mut self, selection: Selection) -> bool {
+        if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::Backspace) {
+            self.delete(selection);
+            return true;
+        }
+        if is_key_pressed(KeyCode::Q) {
+            self.rotate(selection, ROTATE_STEP);
+            return true;
+        }
+        if is_key_pressed(KeyCode::E) {
+            self.rotate(selection, -ROTATE_STEP);
+            return true;
+        }
+        if is_key_pressed(KeyCode::X) {
+            self.scale(selection, SCALE_STEP);
+            return true;
+        }
+        if is_key_pressed(KeyCode::Z) {
+            self.scale(selection, 1.0 / SCALE_STEP);
+            return true;
+        }
+        if is_key_pressed(KeyCode::M) {
+            self.cycle_material(selection);
+            return true;
+        }
+        let properties = [
+            (KeyCode::LeftBracket, 0, -PROPERTY_STEP), (KeyCode::RightBracket, 0, PROPERTY_STEP),
+            (KeyCode::Semicolon, 1, -PROPERTY_STEP), (KeyCode::Apostrophe, 1, PROPERTY_STEP),
+            (KeyCode::Comma, 2, -PROPERTY_STEP), (KeyCode::Period, 2, PROPERTY_STEP),
+        ];
+        for (key, property, delta) in properties {
+            if is_key_pressed(key) {
+                self.tweak_material(selection, property, delta);
+                return true;
+            }
+        }
+        return false;
+    }
+
+    /// Topmost object under `point`: jets, then units, then walls.
+    pub fn pick(&self, point: Vec2) -> Option<Selection> {
+        for (i, jet) in self.scene.jets.iter().enumerate() {
+            let radius = jet.hull.iter().fold(0.0f32, |r, v| r.max(v.length()));
+            if point.distance(jet.pos) <= radius {
+                return Some(Selection::Jet(i));
+            }
+        }
+        for (i, unit) in self.scene.units.iter().enumerate() {
+            if let Some(pos) = unit.pos {
+                if point.distance(pos) <= Self::unit_radius(unit) {
+                    return Some(Selection::Unit(i));
+                }
+            }
+        }
+        for (i, wall) in self.scene.walls.iter().enumerate() {
+            let local = Vec2::from_angle(-wall.rot).rotate(point - wall.pos);
+            if local.x.abs() <= wall.size.x / 2.0 && local.y.abs() <= wall.size.y / 2.0 {
+                return Some(Selection::Wall(i));
+            }
+        }
+        return None;
+    }
+
+    fn place(&mut self, pos: Vec2) -> Selection {
+        match self.tool {
+            Tool::Select | Tool::Unit => {
+                self.scene.units.push(UnitDef { pos: Some(pos), size: Some(SIZE_DEFAULT), ..Default::default() });
+                return Selection::Unit(self.scene.units.len() - 1);
+            },
+            Tool::Wall => {
+                self.scene.walls.push(WallDef { pos, size: Vec2::new(200.0, 20.0), rot: 0.0, material: None });
+                return Selection::Wall(self.scene.walls.len() - 1);
+            },
+            Tool::Jet => {
+                let mut jet = SceneDef::default_scene().jets.remove(0);
+                jet.name = None;
+                jet.pos = pos;
+                self.scene.jets.push(jet);
+                return Selection::Jet(self.scene.jets.len() - 1);
+            },
+        }
+    }
+
+    fn get_pos(&self, selection: Selection) -> Vec2 {
+        match selection {
+            Selection::Wall(i) => self.scene.walls[i].pos,
+            Selection::Unit(i) => self.scene.units[i].pos.unwrap_or_default(),
+            Selection::Jet(i) => self.scene.jets[i].pos,
+        }
+    }
+
+    fn set_pos(&mut self, selection: Selection, pos: Vec2) {
+        match selection {
+            Selection::Wall(i) => self.scene.walls[i].pos = pos,
+            Selection::Unit(i) => self.scene.units[i].pos = Some(pos),
+            Selection::Jet(i) => self.scene.jets[i].pos = pos,
+        }
+    }
+
+    fn rotate(&mut self, selection: Selection, angle: f32) {
+        match selection {
+            Selection::Wall(i) => self.scene.walls[i].rot += angle,
+            Selection::Unit(i) => self.scene.units[i].rot += angle,
+            Selection::Jet(i) => self.scene.jets[i].rot += angle,
+        }
+    }
+
+    fn scale(&mut self, selection: Selection, factor: f32) {
+        match selection {
+            Selection::Wall(i) => self.scene.walls[i].size *= factor,
+            Selection::Unit(i) => {
+                let unit = &mut self.scene.units[i];
+                match unit.parts.is_empty() {
+                    true => unit.size = Some(unit.size.unwrap_or(SIZE_DEFAULT) * factor),
+                    false => for part in unit.parts.iter_mut() {
+                        part.offset *= factor;
+                        part.shape = part.shape.scaled(factor);
+                    },
+                }
+            },
+            Selection::Jet(i) => {
+                let jet = &mut self.scene.jets[i];
+                for v in jet.hull.iter_mut() {
+                    *v *= factor;
+                }
+                for eng in jet.engines.iter_mut() {
+                    eng.pos *= factor;
+                }
+            },
+        }
+    }
+
+    /// Removes the object together with any joint that refers to it by name.
+    fn delete(&mut self, selection: Selection) {
+        let name = match selection {
+            Selection::Wall(i) => {
+                self.scene.walls.remove(i);
+                None
+            },
+            Selection::Unit(i) => self.scene.units.remove(i).name,
+            Selection::Jet(i) => self.scene.jets.remove(i).name,
+        };
+        if let Some(name) = name {
+            self.scene.joints.retain(|joint| joint.a != name && joint.b != name);
+        }
+        self.selected = None;
+        self.drag_offset = None;
+    }
+
+    fn material_slot(&mut self, selection: Selection) -> &mut Option<String> {
+        match selection {
+            Selection::Wall(i) => &mut self.scene.walls[i].material,
+            Selection::Unit(i) => &mut self.scene.units[i].material,
+            Selection::Jet(i) => &mut self.scene.jets[i].material,
+        }
+    }
+
+    fn material_of(&self, selection: Selection) -> &Option<String> {
+        match selection {
+            Selection::Wall(i) => &self.scene.walls[i].material,
+            Selection::Unit(i) => &self.scene.units[i].material,
+            Selection::Jet(i) => &self.scene.jets[i].material,
+        }
+    }
+
+    fn fallback_properties(selection: Selection) -> PhysicsProperities {
+        match selection {
+            Selection::Wall(_) => PhysicsProperities::default().with_layer(Layer::Terrain),
+            Selection::Unit(_) => PhysicsProperities::default(),
+            Selection::Jet(_) => PhysicsProperities::default().with_layer(Layer::Jets),
+        }
+    }
+
+    /// Steps through no material, the scene's own materials and the built-in ones.
+    fn cycle_material(&mut self, selection: Selection) {
+        let mut names: Vec<Option<String>> = vec![None];
+        names.extend(self.scene.materials.keys().cloned().map(Some));
+        names.extend(["default", "bounce", "free"].iter().map(|name| Some(name.to_string())));
+        let slot = self.material_slot(selection);
+        let idx = names.iter().position(|name| name == slot).unwrap_or(0);
+        *slot = names[(idx + 1) % names.len()].clone();
+    }
+
+    /// Adjusts friction (0), restitution (1) or density (2) of the selected object's material.
+    /// Objects without a scene material first get a private copy, so shared built-ins stay untouched.
+    fn tweak_material(&mut self, selection: Selection, property: usize, delta: f32) {
+        let current = self.material_of(selection).clone();
+        let name = match current.filter(|name| self.scene.materials.contains_key(name)) {
+            Some(name) => name,
+            None => {
+                let props = self.scene.material(self.material_of(selection), Self::fallback_properties(selection)).unwrap_or_default();
+                let mut n = 1;
+                while self.scene.materials.contains_key(&format!("custom{}", n)) {
+                    n += 1;
+                }
+                let name = format!("custom{}", n);
+                self.scene.materials.insert(name.clone(), MaterialDef::from_properties(&props));
+                *self.material_slot(selection) = Some(name.clone());
+                name
+            },
+        };
+        let material = self.scene.materials.get_mut(&name).unwrap();
+        match property {
+            0 => material.friction = (material.friction + delta).max(0.0),
+            1 => material.restitution = (material.restitution + delta).clamp(0.0, 1.0),
+            _ => material.density = (material.density + delta).max(PROPERTY_STEP),
+        }
+    }
+
+    /// Selection outline in world space.
+    pub fn draw(&self) {
+        let Some(selection) = self.selected else {
+            return;
+        };
+        match selection {
+            Selection::Wall(i) => {
+                let wall = &self.scene.walls[i];
+                let half = wall.size / 2.0;
+                let corners = [Vec2::new(-half.x, -half.y), Vec2::new(half.x, -half.y), Vec2::new(half.x, half.y), Vec2::new(-half.x, half.y)];
+                for k in 0..4 {
+                    let a = wall.pos + Vec2::from_angle(wall.rot).rotate(corners[k]);
+                    let b = wall.pos + Vec2::from_angle(wall.rot).rotate(corners[(k + 1) % 4]);
+                    draw_line(a.x, a.y, b.x, b.y, 2.0, YELLOW);
+                }
+            },
+            Selection::Unit(i) => {
+                let unit = &self.scene.units[i];
+                let pos = unit.pos.unwrap_or_default();
+                draw_circle_lines(pos.x, pos.y, Self::unit_radius(unit), 2.0, YELLOW);
+            },
+            Selection::Jet(i) => {
+                let jet = &self.scene.jets[i];
+                let radius = jet.hull.iter().fold(0.0f32, |r, v| r.max(v.length()));
+                draw_circle_lines(jet.pos.x, jet.pos.y, radius, 2.0, YELLOW);
+            },
+        }
+    }
+
+    /// Help and status text in screen space.
+    pub fn draw_hud(&self) {
+        let mut lines = vec![
+            format!("EDITOR [{}]  1 select  2 unit  3 wall  4 jet  ctrl+S save '{}'  tab exit", self.tool.name(), self.path),
+            "drag move  Q/E rotate  Z/X scale  del delete  M material  [ ] friction  ; ' restitution  , . density".to_string(),
+        ];
+        if let Some(selection) = self.selected {
+            let material = self.material_of(selection);
+            let props = self.scene.material(material, Self::fallback_properties(selection)).unwrap_or_default();
+            lines.push(format!("{:?}  material: {}  friction {:.2}  restitution {:.2}  density {:.2}  layer {:?}",
+                selection, material.as_deref().unwrap_or("-"), props.friction, props.restitution, props.density, props.layer));
+        }
+        if !self.status.is_empty() {
+            lines.push(self.status.clone());
+        }
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 10.0, 20.0 + 18.0 * i as f32, 18.0, WHITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unit::PartShape;
+
+    #[test]
+    fn random_and_counted_units_are_selectable() {
+        let editor = Editor::new(SceneDef::default_scene(), None, 7);
+        let expected: usize = SceneDef::default_scene().units.iter().map(|unit| unit.count.unwrap_or(1)).sum();
+        assert_eq!(editor.scene.units.len(), expected);
+        for (i, unit) in editor.scene.units.iter().enumerate() {
+            assert_eq!(unit.count, None);
+            let picked = editor.pick(unit.pos.expect("expanded units have a position"));
+            assert!(matches!(picked, Some(Selection::Unit(_)) | Some(Selection::Jet(_))), "unit {} can't be picked", i);
+        }
+    }
+
+    #[test]
+    fn scaling_a_parts_unit_scales_its_parts() {
+        let mut scene = SceneDef::default();
+        let part = BodyPartDef { shape: PartShape::Ball { radius: 4.0 }, offset: Vec2::new(10.0, 0.0), rot: 0.0, color: None, material: None };
+        scene.units.push(UnitDef { pos: Some(Vec2::new(100.0, 100.0)), parts: vec![part], ..Default::default() });
+        let mut editor = Editor::new(scene, None, 1);
+        editor.scale(Selection::Unit(0), 2.0);
+        let part = &editor.scene.units[0].parts[0];
+        assert_eq!(part.offset, Vec2::new(20.0, 0.0));
+        assert_eq!(part.shape, PartShape::Ball { radius: 8.0 });
+    }
+}
@@ -0,0 +1,161 @@
+#![allow(unused)]
+
+use crate::util::*;
+use crate::physics::*;
+use crate::unit::Unit;
+use crate::jet::Jet;
+use crate::events::Contacts;
+use macroquad::{color, prelude::*};
+use rapier2d::geometry::*;
+use rapier2d::prelude::RigidBodyHandle;
+use serde::{Deserialize, Serialize};
+
+
+/// State every entity shares: identity, the last two physics poses, look, rigid body and contacts.
+#[derive(Serialize, Deserialize)]
+pub struct Body {
+    pub key: u64,
+    pub pos: Vec2,
+    pub rot: f32,
+    pub prev_pos: Vec2,
+    pub prev_rot: f32,
+    #[serde(with = "serde_color")]
+    pub color: color::Color,
+    pub shape: SharedShape,
+    pub physics_handle: RigidBodyHandle,
+    #[serde(default)]
+    pub contacts: Contacts,
+}
+
+impl Body {
+
+    pub fn new(key: u64, pos: Vec2, rot: f32, color: Color, shape: SharedShape, physics_handle: RigidBodyHandle) -> Self {
+        Self {
+            key,
+            pos,
+            rot,
+            prev_pos: pos,
+            prev_rot: rot,
+            color,
+            shape,
+            physics_handle,
+            contacts: Contacts::default(),
+        }
+    }
+
+    pub fn interpolate(&self, alpha: f32) -> (Vec2, f32) {
+        return (self.prev_pos.lerp(self.pos, alpha), lerp_angle(self.prev_rot, self.rot, alpha));
+    }
+
+    /// Copies the rigid body's pose in, keeping the old one for interpolation.
+    pub fn sync(&mut self, physics: &Physics) {
+        let physics_data = physics.get_physics_data(self.physics_handle);
+        self.prev_pos = self.pos;
+        self.prev_rot = self.rot;
+        self.pos = physics_data.position;
+        self.rot = physics_data.rotation;
+    }
+
+    /// Teleports the body to `pos`, shifting the previous pose along so drawing doesn't streak.
+    pub fn relocate(&mut self, pos: Vec2, physics: &mut Physics) {
+        physics.set_body_position(self.physics_handle, pos);
+        self.prev_pos += pos - self.pos;
+        self.pos = pos;
+    }
+}
+
+/// Behaviour of one kind of simulated object; the shared bookkeeping lives in its `Body`.
+pub trait Entity {
+
+    fn body(&self) -> &Body;
+
+    fn body_mut(&mut self) -> &mut Body;
+
+    /// Draws the entity at an explicit pose.
+    fn draw_at(&self, pos: Vec2, rot: f32);
+
+    /// Radius of a circle that encloses the whole entity.
+    fn get_extent(&self) -> f32;
+
+    /// Runs once per physics step, before the world is stepped.
+    fn update(&mut self, dt: f32, physics: &mut Physics) {
+    }
+
+    fn key(&self) -> u64 {
+        return self.body().key;
+    }
+
+    fn sync(&mut self, physics: &Physics) {
+        self.body_mut().sync(physics);
+    }
+
+    /// Draws the entity blended `alpha` of the way from its previous to its current physics state.
+    fn draw(&self, alpha: f32) {
+        self.draw_shifted(alpha, Vec2::ZERO);
+    }
+
+    /// Same as `draw`, displaced by `shift`; used for ghost copies across toroidal seams.
+    fn draw_shifted(&self, alpha: f32, shift: Vec2) {
+        let (pos, rot) = self.body().interpolate(alpha);
+        self.draw_at(pos + shift, rot);
+    }
+}
+
+/// Registry slot holding any entity kind; a new kind needs a variant here and an `Entity` impl.
+/// Jets are few and units many, so the size gap between variants isn't worth boxing over.
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize)]
+pub enum Object {
+    Unit(Unit),
+    Jet(Jet),
+}
+
+impl Object {
+
+    pub fn entity(&self) -> &dyn Entity {
+        match self {
+            Object::Unit(unit) => unit,
+            Object::Jet(jet) => jet,
+        }
+    }
+
+    pub fn entity_mut(&mut self) -> &mut dyn Entity {
+        match self {
+            Object::Unit(unit) => unit,
+            Object::Jet(jet) => jet,
+        }
+    }
+
+    pub fn as_unit(&self) -> Option<&Unit> {
+        match self {
+            Object::Unit(unit) => Some(unit),
+            _ => None,
+        }
+    }
+
+    pub fn as_jet(&self) -> Option<&Jet> {
+        match self {
+            Object::Jet(jet) => Some(jet),
+            _ => None,
+        }
+    }
+
+    pub fn as_jet_mut(&mut self) -> Option<&mut Jet> {
+        match self {
+            Object::Jet(jet) => Some(jet),
+            _ => None,
+        }
+    }
+}
+
+impl From<Unit> for Object {
+    fn from(unit: Unit) -> Self {
+        return Object::Unit(unit);
+    }
+}
+
+impl From<Jet> for Object {
+    fn from(jet: Jet) -> Self {
+        return Object::Jet(jet);
+    }
+}
@@ -0,0 +1,325 @@
+#![allow(unused)]
+
+use std::f32::consts::PI;
+use std::fmt;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::consts::*;
+use crate::controller::*;
+use crate::entity::Entity;
+use crate::events::SimEvent;
+use crate::jet::JetEng;
+use crate::mixer::EngineSetting;
+use crate::physics::PhysicsData;
+use crate::sensors::RangeSensor;
+use crate::rng::SimRng;
+use crate::scene::SceneDef;
+use crate::sim::Simulation;
+
+/// Distance from the target, in px, that counts as arriving.
+pub const TARGET_RADIUS: f32 = 20.0;
+/// How far from the jet's start a reach-target goal may be placed, in px.
+const TARGET_RANGE: f32 = 300.0;
+/// Leaving the world by more than this ends the episode.
+const OUT_OF_BOUNDS: f32 = 100.0;
+/// Scales that bring observations to roughly -1..1.
+const SPEED_SCALE: f32 = 100.0;
+const SPIN_SCALE: f32 = 5.0;
+
+/// What the agent is rewarded for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Task {
+    /// Fly to a random point near the start; rewarded for closing in, with a bonus on arrival.
+    ReachTarget,
+    /// Stay over the start point, as still as possible.
+    Hover,
+    /// Survive among the units; any collision ends the episode.
+    AvoidUnits,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Task::ReachTarget => write!(f, "reach"),
+            Task::Hover => write!(f, "hover"),
+            Task::AvoidUnits => write!(f, "avoid"),
+        }
+    }
+}
+
+impl Task {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "reach" => Some(Task::ReachTarget),
+            "hover" => Some(Task::Hover),
+            "avoid" => Some(Task::AvoidUnits),
+            _ => None,
+        }
+    }
+}
+
+/// Box space: every dimension lies in `low[i]..=high[i]`, either end possibly infinite.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Space {
+    pub low: Vec<f32>,
+    pub high: Vec<f32>,
+}
+
+impl Space {
+
+    pub fn dim(&self) -> usize {
+        return self.low.len();
+    }
+
+    pub fn contains(&self, values: &[f32]) -> bool {
+        return values.len() == self.dim() && values.iter().zip(self.low.iter().zip(self.high.iter())).all(|(v, (lo, hi))| lo <= v && v <= hi);
+    }
+
+    pub fn clip(&self, values: &[f32]) -> Vec<f32> {
+        return values.iter().zip(self.low.iter().zip(self.high.iter())).map(|(v, (lo, hi))| v.clamp(*lo, *hi)).collect();
+    }
+
+    /// Maps values in -1..1, e.g. a network's outputs, linearly onto the space; only meaningful for bounded spaces.
+    pub fn scale(&self, unit: &[f32]) -> Vec<f32> {
+        return unit.iter().zip(self.low.iter().zip(self.high.iter())).map(|(u, (lo, hi))| lo + (u.clamp(-1.0, 1.0) + 1.0) / 2.0 * (hi - lo)).collect();
+    }
+
+    /// Uniform sample; only meaningful for bounded spaces.
+    pub fn sample(&self, rng: &mut SimRng) -> Vec<f32> {
+        return self.low.iter().zip(self.high.iter()).map(|(lo, hi)| rng.gen_range(*lo, *hi)).collect();
+    }
+}
+
+/// Extra facts about a step, for logging rather than learning.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StepInfo {
+    pub step: u64,
+    /// Distance to the target; zero for tasks without one.
+    pub distance: f32,
+    pub collisions: usize,
+    /// The episode hit `max_steps` rather than ending on its own.
+    pub truncated: bool,
+}
+
+/// Result of one `JetEnv::step`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub observation: Vec<f32>,
+    pub reward: f32,
+    pub done: bool,
+    pub info: StepInfo,
+}
+
+/// Reinforcement learning environment around a headless `Simulation`. The agent flies the scene's first jet through an
+/// `External` controller: actions are engine throttles, then nozzle angles for gimballed engines.
+/// Observations are the offset to the target and the velocity, both in the jet's frame, its heading and spin, and every
+/// range sensor beam as a fraction of its range.
+pub struct JetEnv {
+    pub task: Task,
+    pub scene: SceneDef,
+    /// Physics steps per `step` call.
+    pub frame_skip: u32,
+    pub max_steps: u64,
+    sim: Option<Simulation>,
+    jet: u64,
+    target: Vec2,
+    steps: u64,
+    distance: f32,
+}
+
+impl JetEnv {
+
+    pub fn new(task: Task, scene: SceneDef) -> Self {
+        Self { task, scene, frame_skip: 1, max_steps: 600, sim: None, jet: 0, target: Vec2::ZERO, steps: 0, distance: 0.0 }
+    }
+
+    pub fn get_sim(&self) -> Option<&Simulation> {
+        return self.sim.as_ref();
+    }
+
+    pub fn get_target(&self) -> Vec2 {
+        return self.target;
+    }
+
+    /// Hands over the world of the current episode, e.g. to watch it in the window.
+    pub fn into_sim(self) -> Option<Simulation> {
+        return self.sim;
+    }
+
+    /// Rebuilds the world from `seed` and returns the first observation.
+    pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+        let mut sim = Simulation::from_scene(seed, &self.scene).unwrap_or_else(|err| panic!("can't build the environment scene: {}", err));
+        let jet = sim.get_jets().next().expect("the environment scene needs a jet");
+        let (key, start) = (jet.key(), jet.body.pos);
+        // A separate stream, so the world is the same whatever the task.
+        let mut rng = SimRng::new(seed ^ 0x5eed_7a59);
+        self.target = match self.task {
+            Task::ReachTarget => start + Vec2::from_angle(rng.gen_range(-PI, PI)) * rng.gen_range(TARGET_RADIUS * 3.0, TARGET_RANGE),
+            Task::Hover | Task::AvoidUnits => start,
+        };
+        if let Some(jet) = sim.get_jet_mut(key) {
+            jet.set_controller(Pilot::External(External::default()));
+        }
+        self.jet = key;
+        self.steps = 0;
+        self.sim = Some(sim);
+        self.distance = self.target.distance(start);
+        return self.observe();
+    }
+
+    pub fn observation_space(&self) -> Space {
+        let ranges = self.range_beams();
+        let mut low = vec![f32::NEG_INFINITY; 7];
+        let mut high = vec![f32::INFINITY; 7];
+        low[4] = -1.0;
+        high[4] = 1.0;
+        low[5] = -1.0;
+        high[5] = 1.0;
+        low.extend(std::iter::repeat_n(0.0, ranges));
+        high.extend(std::iter::repeat_n(1.0, ranges));
+        return Space { low, high };
+    }
+
+    pub fn action_space(&self) -> Space {
+        let jet = self.sim.as_ref().and_then(|sim| sim.get_jet(self.jet));
+        return action_space(jet.map(|jet| jet.engines.as_slice()).unwrap_or(&[]));
+    }
+
+    /// Applies `action`, clipped to the action space, for `frame_skip` physics steps.
+    pub fn step(&mut self, action: &[f32]) -> Transition {
+        let action = self.action_space().clip(action);
+        let sim = self.sim.as_mut().expect("reset the environment before stepping it");
+        let Some(jet) = sim.get_jet_mut(self.jet) else {
+            return Transition { observation: vec![], reward: 0.0, done: true, info: StepInfo::default() };
+        };
+        let settings = engine_settings(&jet.engines, &action);
+        jet.set_controller(Pilot::External(External { action: Actuation::Engines(settings) }));
+        let mut collisions = 0;
+        for _ in 0..self.frame_skip {
+            sim.step();
+            collisions += sim.step_events().iter().filter(|event| match event {
+                SimEvent::CollisionStarted { a, b } => *a == self.jet || *b == self.jet,
+                _ => false,
+            }).count();
+        }
+        self.steps += 1;
+        let Some(jet) = sim.get_jet(self.jet) else {
+            return Transition { observation: vec![], reward: 0.0, done: true, info: StepInfo::default() };
+        };
+        let state = sim.physics.get_physics_data(jet.body.physics_handle);
+        let distance = state.position.distance(self.target);
+        let margin = OUT_OF_BOUNDS;
+        let out = state.position.x < -margin || state.position.y < -margin || state.position.x > WORLD_W + margin || state.position.y > WORLD_H + margin;
+        let (mut reward, mut done) = match self.task {
+            Task::ReachTarget => {
+                let arrived = distance < TARGET_RADIUS;
+                ((self.distance - distance) / TARGET_RADIUS - 0.01 + if arrived { 10.0 } else { 0.0 }, arrived)
+            },
+            Task::Hover => (1.0 - distance / TARGET_RANGE - 0.1 * state.velocity.length() / SPEED_SCALE - 0.05 * state.spin.abs(), false),
+            Task::AvoidUnits => match collisions > 0 {
+                true => (-10.0, true),
+                false => (0.1, false),
+            },
+        };
+        if out {
+            reward -= 10.0;
+            done = true;
+        }
+        self.distance = distance;
+        let truncated = !done && self.steps >= self.max_steps;
+        let info = StepInfo { step: self.steps, distance: if self.task == Task::AvoidUnits { 0.0 } else { distance }, collisions, truncated };
+        return Transition { observation: self.observe(), reward, done: done || truncated, info };
+    }
+
+    fn range_beams(&self) -> usize {
+        let jet = self.sim.as_ref().and_then(|sim| sim.get_jet(self.jet));
+        return jet.map(|jet| jet.sensors.iter().map(|sensor| sensor.beams).sum()).unwrap_or(0);
+    }
+
+    fn observe(&self) -> Vec<f32> {
+        let Some(sim) = self.sim.as_ref() else {
+            return vec![];
+        };
+        let Some(jet) = sim.get_jet(self.jet) else {
+            return vec![];
+        };
+        let state = sim.physics.get_physics_data(jet.body.physics_handle);
+        return observe(&state, self.target, &jet.sensors);
+    }
+}
+
+/// Throttle limits of every engine, then nozzle limits of the gimballed ones.
+pub fn action_space(engines: &[JetEng]) -> Space {
+    let (mut low, mut high) = (vec![], vec![]);
+    for eng in engines.iter() {
+        let (lo, hi) = eng.throttle_limits();
+        low.push(lo);
+        high.push(hi);
+    }
+    for eng in engines.iter().filter(|eng| eng.gimbal_limit > 0.0) {
+        low.push(-eng.gimbal_limit);
+        high.push(eng.gimbal_limit);
+    }
+    return Space { low, high };
+}
+
+/// Splits an action laid out as in `action_space` into per-engine settings.
+pub fn engine_settings(engines: &[JetEng], action: &[f32]) -> Vec<EngineSetting> {
+    let mut angles = action[engines.len().min(action.len())..].iter();
+    return engines.iter().zip(action.iter()).map(|(eng, throttle)| {
+        let angle = if eng.gimbal_limit > 0.0 { angles.next().copied().unwrap_or(0.0) } else { 0.0 };
+        return EngineSetting { throttle: *throttle, angle };
+    }).collect();
+}
+
+/// Observation vector for a jet in `state` heading for `target`; see `JetEnv`.
+pub fn observe(state: &PhysicsData, target: Vec2, ranges: &[RangeSensor]) -> Vec<f32> {
+    let offset = Vec2::from_angle(-state.rotation).rotate(target - state.position) / TARGET_RANGE;
+    let velocity = Vec2::from_angle(-state.rotation).rotate(state.velocity) / SPEED_SCALE;
+    let mut observation = vec![offset.x, offset.y, velocity.x, velocity.y, state.rotation.sin(), state.rotation.cos(), state.spin / SPIN_SCALE];
+    for sensor in ranges.iter() {
+        observation.extend(sensor.get_readings().iter().map(|reading| reading.distance / sensor.range));
+    }
+    return observation;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space() -> Space {
+        return Space { low: vec![-1.0, 0.1, -0.5], high: vec![1.0, 1.0, 0.5] };
+    }
+
+    #[test]
+    fn clip_keeps_values_inside() {
+        let space = space();
+        assert_eq!(space.clip(&[-3.0, 0.5, 0.7]), vec![-1.0, 0.5, 0.5]);
+        assert_eq!(space.clip(&[0.25, 0.0, -0.5]), vec![0.25, 0.1, -0.5]);
+        assert!(space.contains(&space.clip(&[9.0, -9.0, f32::MAX])));
+    }
+
+    #[test]
+    fn scale_maps_unit_range_onto_bounds() {
+        let space = space();
+        assert_eq!(space.scale(&[-1.0, -1.0, -1.0]), space.low);
+        assert_eq!(space.scale(&[1.0, 1.0, 1.0]), space.high);
+        assert_eq!(space.scale(&[0.0, 0.0, 0.0]), vec![0.0, 0.55, 0.0]);
+        // Outside -1..1 is clamped rather than extrapolated.
+        assert_eq!(space.scale(&[-5.0, 5.0, 0.5]), vec![-1.0, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn action_space_lists_throttles_then_gimbals() {
+        let engines = vec![
+            JetEng::new(Vec2::new(-10.0, 0.0), Vec2::X, 100.0).with_gimbal(0.3, 1.0),
+            JetEng::new(Vec2::new(0.0, 5.0), Vec2::Y, 50.0).with_spool(0.2, 0.0, 0.0),
+        ];
+        let space = action_space(&engines);
+        assert_eq!(space.low, vec![-1.0, 0.2, -0.3]);
+        assert_eq!(space.high, vec![1.0, 1.0, 0.3]);
+        let settings = engine_settings(&engines, &[0.5, 0.4, -0.2]);
+        assert_eq!(settings, vec![EngineSetting { throttle: 0.5, angle: -0.2 }, EngineSetting { throttle: 0.4, angle: 0.0 }]);
+    }
+}
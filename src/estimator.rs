@@ -0,0 +1,370 @@
+#![allow(unused)]
+
+use macroquad::prelude::*;
+use rapier2d::na::{Matrix2, Matrix4, SMatrix, SVector, Vector2, Vector4};
+use rapier2d::prelude::RigidBodyHandle;
+use serde::{Deserialize, Serialize};
+use crate::mixer::Wrench;
+use crate::physics::Physics;
+use crate::sensors::*;
+
+/// `[x, y, vx, vy, heading, spin, gyro bias]`.
+type State = SVector<f32, 7>;
+type Covariance = SMatrix<f32, 7, 7>;
+/// The state followed by the accelerometer's `[velocity change, bias]`.
+type Augmented = SMatrix<f32, 11, 11>;
+
+/// Process noise of the filter: how far the real jet may stray from the thrust model between steps.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EkfSpec {
+    /// Unmodelled linear acceleration, e.g. from contacts, in px/s².
+    pub accel_noise: f32,
+    /// Unmodelled angular acceleration, in rad/s².
+    pub spin_noise: f32,
+    /// Gyro bias drift, in rad/s per square root of a second.
+    pub gyro_walk: f32,
+    /// Accelerometer bias drift, in px/s² per square root of a second.
+    pub accel_walk: f32,
+    /// Initial velocity uncertainty, in px/s; the jet starts with an unknown push.
+    pub initial_speed: f32,
+}
+
+impl Default for EkfSpec {
+    fn default() -> Self {
+        Self { accel_noise: 10.0, spin_noise: 2.0, gyro_walk: 0.002, accel_walk: 0.5, initial_speed: 100.0 }
+    }
+}
+
+/// Estimated jet state, in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub rotation: f32,
+    pub spin: f32,
+}
+
+/// What the filter keeps for the accelerometer once it has taken a first sample: the velocity change since that
+/// sample, which the next one measures, and the accelerometer's bias. Tracking the change itself rather than a copy of
+/// the old velocity keeps its small variance from being the difference of two large ones.
+#[derive(Clone, Serialize, Deserialize)]
+struct AccelTrack {
+    /// Sensor time of the last sample.
+    since: f32,
+    change: Vector2<f32>,
+    bias: Vector2<f32>,
+    /// Covariance of `[change, bias]`.
+    covariance: Matrix4<f32>,
+    /// Cross-covariance of the state with `[change, bias]`.
+    cross: SMatrix<f32, 7, 4>,
+}
+
+/// Extended Kalman filter over position, velocity, heading, spin and gyro bias. It predicts with the thrust the engines
+/// produce and the body's mass, inertia and damping, and corrects with the gyro, the accelerometer and latency-compensated
+/// position fixes. The accelerometer catches what the thrust model misses, such as contacts.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Ekf {
+    pub spec: EkfSpec,
+    state: State,
+    covariance: Covariance,
+    #[serde(default)]
+    accel: Option<AccelTrack>,
+}
+
+impl Ekf {
+
+    pub fn new(spec: EkfSpec, position: Vec2, rotation: f32) -> Self {
+        let state = State::from([position.x, position.y, 0.0, 0.0, rotation, 0.0, 0.0]);
+        let speed = spec.initial_speed * spec.initial_speed;
+        let covariance = Covariance::from_diagonal(&State::from([1.0, 1.0, speed, speed, 0.01, 0.1, 1e-4]));
+        Self { spec, state, covariance, accel: None }
+    }
+
+    pub fn get_estimate(&self) -> Estimate {
+        let x = &self.state;
+        let rotation = x[4].sin().atan2(x[4].cos());
+        return Estimate { position: Vec2::new(x[0], x[1]), velocity: Vec2::new(x[2], x[3]), rotation, spin: x[5] };
+    }
+
+    pub fn get_gyro_bias(&self) -> f32 {
+        return self.state[6];
+    }
+
+    /// Estimated accelerometer bias, once the accelerometer has been sampled.
+    pub fn get_accel_bias(&self) -> Option<Vec2> {
+        return self.accel.as_ref().map(|accel| Vec2::new(accel.bias.x, accel.bias.y));
+    }
+
+    pub fn get_covariance(&self) -> &Covariance {
+        return &self.covariance;
+    }
+
+    /// Position block of the covariance, in px².
+    pub fn get_position_covariance(&self) -> Matrix2<f32> {
+        return self.covariance.fixed_view::<2, 2>(0, 0).into_owned();
+    }
+
+    /// Runs one filter step: predicts over `dt` with `thrust` (body-frame force and torque), then folds in whatever
+    /// the IMU and GPS delivered this step.
+    pub fn update(&mut self, dt: f32, thrust: Wrench, physics: &Physics, handle: RigidBodyHandle, imu: Option<&Imu>, gps: Option<&Gps>) {
+        self.predict(dt, thrust, physics, handle);
+        if let Some(imu) = imu.filter(|imu| imu.is_fresh()) {
+            if let Some(reading) = imu.get_reading() {
+                self.correct_gyro(reading.gyro, imu.spec.gyro_noise);
+                self.correct_accel(reading.accel, reading.time, imu.spec.accel_noise, physics.get_gravity());
+            }
+        }
+        if let Some(gps) = gps.filter(|gps| gps.is_fresh()) {
+            if let Some(reading) = gps.get_reading() {
+                self.correct_position(reading.pos, gps.get_time() - reading.time, gps.spec.noise);
+            }
+        }
+    }
+
+    fn predict(&mut self, dt: f32, thrust: Wrench, physics: &Physics, handle: RigidBodyHandle) {
+        let Some(rb) = physics.rigid_bodies.get(handle) else {
+            return;
+        };
+        let mass = rb.mass().max(f32::EPSILON);
+        let inertia = rb.mass_properties().effective_angular_inertia().max(f32::EPSILON);
+        let (damping, angular_damping) = (rb.linear_damping(), rb.angular_damping());
+        let x = self.state;
+        let accel = Vec2::from_angle(x[4]).rotate(Vec2::new(thrust.forward, thrust.lateral) / mass) + physics.get_gravity();
+        let angular_accel = thrust.torque / inertia;
+        // Integrated the way the physics does it: accelerate, damp, then move with the new velocities.
+        let (decay, angular_decay) = (1.0 / (1.0 + damping * dt), 1.0 / (1.0 + angular_damping * dt));
+        let velocity = (Vec2::new(x[2], x[3]) + accel * dt) * decay;
+        let spin = (x[5] + angular_accel * dt) * angular_decay;
+        self.state = State::from([x[0] + velocity.x * dt, x[1] + velocity.y * dt, velocity.x, velocity.y, x[4] + spin * dt, spin, x[6]]);
+        // Turning the thrust vector is the only non-linear part: d(R a)/d(heading) is R a turned a quarter.
+        let turned = (accel - physics.get_gravity()).perp();
+        let mut jacobian = Covariance::identity();
+        jacobian[(2, 2)] = decay;
+        jacobian[(3, 3)] = decay;
+        jacobian[(2, 4)] = turned.x * dt * decay;
+        jacobian[(3, 4)] = turned.y * dt * decay;
+        jacobian[(5, 5)] = angular_decay;
+        for (pos, vel) in [(0, 2), (1, 3), (4, 5)] {
+            let row = jacobian.row(vel) * dt;
+            let mut pos_row = jacobian.row_mut(pos);
+            pos_row += row;
+        }
+        let mut noise = Covariance::zeros();
+        let (qa, qs) = (self.spec.accel_noise.powi(2), self.spec.spin_noise.powi(2));
+        for (pos, vel, q) in [(0, 2, qa), (1, 3, qa), (4, 5, qs)] {
+            noise[(pos, pos)] = q * dt.powi(3) / 3.0;
+            noise[(pos, vel)] = q * dt.powi(2) / 2.0;
+            noise[(vel, pos)] = q * dt.powi(2) / 2.0;
+            noise[(vel, vel)] = q * dt;
+        }
+        noise[(6, 6)] = self.spec.gyro_walk.powi(2) * dt;
+        let Some(track) = self.accel.as_mut() else {
+            self.covariance = jacobian * self.covariance * jacobian.transpose() + noise;
+            return;
+        };
+        track.change += Vector2::new(self.state[2] - x[2], self.state[3] - x[3]);
+        // The velocity change grows by whatever the velocity did this step, noise included; the bias wanders.
+        let mut jacobian_aug = Augmented::identity();
+        jacobian_aug.fixed_view_mut::<7, 7>(0, 0).copy_from(&jacobian);
+        let growth = jacobian.fixed_rows::<2>(2) - Covariance::identity().fixed_rows::<2>(2);
+        jacobian_aug.fixed_view_mut::<2, 7>(7, 0).copy_from(&growth);
+        let mut noise_aug = Augmented::zeros();
+        noise_aug.fixed_view_mut::<7, 7>(0, 0).copy_from(&noise);
+        noise_aug.fixed_view_mut::<7, 2>(0, 7).copy_from(&noise.fixed_columns::<2>(2));
+        noise_aug.fixed_view_mut::<2, 7>(7, 0).copy_from(&noise.fixed_rows::<2>(2));
+        noise_aug.fixed_view_mut::<2, 2>(7, 7).copy_from(&noise.fixed_view::<2, 2>(2, 2));
+        noise_aug[(9, 9)] = self.spec.accel_walk.powi(2) * dt;
+        noise_aug[(10, 10)] = self.spec.accel_walk.powi(2) * dt;
+        let covariance = jacobian_aug * Self::augment(&self.covariance, track) * jacobian_aug.transpose() + noise_aug;
+        Self::split(&covariance, &mut self.covariance, track);
+    }
+
+    fn correct_gyro(&mut self, gyro: f32, noise: f32) {
+        let mut h = SMatrix::<f32, 1, 7>::zeros();
+        h[(0, 5)] = 1.0;
+        h[(0, 6)] = 1.0;
+        let innovation = SMatrix::<f32, 1, 1>::new(gyro - self.state[5] - self.state[6]);
+        self.correct(&h, innovation, SMatrix::<f32, 1, 1>::new(noise.powi(2).max(1e-6)));
+    }
+
+    /// The accelerometer reports the average specific force since its previous sample, so with its bias taken off,
+    /// turned into the world and gravity added back it measures how much the velocity changed in between.
+    fn correct_accel(&mut self, accel: Vec2, time: f32, noise: f32, gravity: Vec2) {
+        let Some(track) = self.accel.as_ref() else {
+            // Nothing to compare the first sample with; start tracking from it with the bias unknown to about 1 px/s².
+            let covariance = Matrix4::from_diagonal(&Vector4::new(0.0, 0.0, 1.0, 1.0));
+            self.accel = Some(AccelTrack { since: time, change: Vector2::zeros(), bias: Vector2::zeros(), covariance, cross: SMatrix::zeros() });
+            return;
+        };
+        let elapsed = time - track.since;
+        if elapsed > 0.0 {
+            // The IMU turns its reading into the body frame with the heading at the time of the sample.
+            let rotation = Vec2::from_angle(self.state[4]);
+            let force = rotation.rotate(accel - Vec2::new(track.bias.x, track.bias.y));
+            let change = (force + gravity) * elapsed;
+            let turned = force.perp() * elapsed;
+            let mut h = SMatrix::<f32, 2, 11>::zeros();
+            h[(0, 4)] = -turned.x;
+            h[(1, 4)] = -turned.y;
+            h[(0, 7)] = 1.0;
+            h[(1, 8)] = 1.0;
+            // Rotating the bias into the world: its columns are the rotation matrix.
+            h[(0, 9)] = rotation.x * elapsed;
+            h[(1, 9)] = rotation.y * elapsed;
+            h[(0, 10)] = -rotation.y * elapsed;
+            h[(1, 10)] = rotation.x * elapsed;
+            let innovation = Vector2::new(change.x, change.y) - track.change;
+            self.correct_augmented(&h, innovation, Matrix2::identity() * (noise * elapsed).powi(2).max(1e-6));
+        }
+        // The next sample measures the change from here on.
+        let Some(track) = self.accel.as_mut() else {
+            return;
+        };
+        track.since = time;
+        track.change = Vector2::zeros();
+        track.covariance.fixed_rows_mut::<2>(0).fill(0.0);
+        track.covariance.fixed_columns_mut::<2>(0).fill(0.0);
+        track.cross.fixed_columns_mut::<2>(0).fill(0.0);
+    }
+
+    /// A fix measured `lag` seconds ago is compared with where the estimate says the jet was then.
+    fn correct_position(&mut self, pos: Vec2, lag: f32, noise: f32) {
+        let mut h = SMatrix::<f32, 2, 7>::zeros();
+        h[(0, 0)] = 1.0;
+        h[(1, 1)] = 1.0;
+        h[(0, 2)] = -lag;
+        h[(1, 3)] = -lag;
+        let predicted = Vector2::new(self.state[0] - self.state[2] * lag, self.state[1] - self.state[3] * lag);
+        let innovation = Vector2::new(pos.x, pos.y) - predicted;
+        self.correct(&h, innovation, Matrix2::identity() * noise.powi(2).max(1e-6));
+    }
+
+    fn correct<const M: usize>(&mut self, h: &SMatrix<f32, M, 7>, innovation: SMatrix<f32, M, 1>, noise: SMatrix<f32, M, M>) {
+        if self.accel.is_some() {
+            let mut augmented = SMatrix::<f32, M, 11>::zeros();
+            augmented.fixed_view_mut::<M, 7>(0, 0).copy_from(h);
+            self.correct_augmented(&augmented, innovation, noise);
+            return;
+        }
+        let s = h * self.covariance * h.transpose() + noise;
+        let Some(s_inv) = s.try_inverse() else {
+            return;
+        };
+        let gain = self.covariance * h.transpose() * s_inv;
+        self.state += gain * innovation;
+        // Joseph form keeps the covariance symmetric and positive with f32 round-off.
+        let i_kh = Covariance::identity() - gain * h;
+        self.covariance = i_kh * self.covariance * i_kh.transpose() + gain * noise * gain.transpose();
+    }
+
+    /// Same update over the state and the accelerometer's velocity change and bias together, keeping them consistent.
+    fn correct_augmented<const M: usize>(&mut self, h: &SMatrix<f32, M, 11>, innovation: SMatrix<f32, M, 1>, noise: SMatrix<f32, M, M>) {
+        let Some(track) = self.accel.as_mut() else {
+            return;
+        };
+        let covariance = Self::augment(&self.covariance, track);
+        let s = h * covariance * h.transpose() + noise;
+        let Some(s_inv) = s.try_inverse() else {
+            return;
+        };
+        let gain = covariance * h.transpose() * s_inv;
+        let step = gain * innovation;
+        self.state += step.fixed_rows::<7>(0);
+        track.change += step.fixed_rows::<2>(7);
+        track.bias += step.fixed_rows::<2>(9);
+        let i_kh = Augmented::identity() - gain * h;
+        let covariance = i_kh * covariance * i_kh.transpose() + gain * noise * gain.transpose();
+        Self::split(&covariance, &mut self.covariance, track);
+    }
+
+    fn augment(covariance: &Covariance, track: &AccelTrack) -> Augmented {
+        let mut augmented = Augmented::zeros();
+        augmented.fixed_view_mut::<7, 7>(0, 0).copy_from(covariance);
+        augmented.fixed_view_mut::<7, 4>(0, 7).copy_from(&track.cross);
+        augmented.fixed_view_mut::<4, 7>(7, 0).copy_from(&track.cross.transpose());
+        augmented.fixed_view_mut::<4, 4>(7, 7).copy_from(&track.covariance);
+        return augmented;
+    }
+
+    fn split(augmented: &Augmented, covariance: &mut Covariance, track: &mut AccelTrack) {
+        *covariance = augmented.fixed_view::<7, 7>(0, 0).into_owned();
+        track.cross = augmented.fixed_view::<7, 4>(0, 7).into_owned();
+        track.covariance = augmented.fixed_view::<4, 4>(7, 7).into_owned();
+    }
+
+    /// Draws the estimated position and heading with its 2-sigma position ellipse.
+    pub fn draw(&self) {
+        let estimate = self.get_estimate();
+        let eigen = self.get_position_covariance().symmetric_eigen();
+        let (a, b) = (eigen.eigenvalues[0].max(0.0).sqrt() * 2.0, eigen.eigenvalues[1].max(0.0).sqrt() * 2.0);
+        let axis = eigen.eigenvectors.column(0);
+        let angle = axis[1].atan2(axis[0]);
+        let pos = estimate.position;
+        draw_ellipse_lines(pos.x, pos.y, a.max(1.0), b.max(1.0), angle.to_degrees(), 1.0, MAGENTA);
+        let nose = pos + Vec2::from_angle(estimate.rotation) * 12.0;
+        draw_line(pos.x, pos.y, nose.x, nose.y, 1.0, MAGENTA);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapier2d::prelude::SharedShape;
+    use crate::consts::FIX_DT;
+    use crate::physics::PhysicsProperities;
+
+    /// Flies a body with known thrust, plus `push` the filter isn't told about, for `steps` steps and returns the
+    /// filter's final position, velocity and heading errors.
+    fn fly(imu: Option<ImuSpec>, gps: Option<GpsSpec>, push: Vec2, steps: usize) -> (f32, f32, f32) {
+        let mut physics = Physics::new();
+        let props = PhysicsProperities { linear_damping: 0.1, angular_damping: 0.9, ..PhysicsProperities::default() };
+        let start = Vec2::new(400.0, 300.0);
+        let handle = physics.add_dynamic_rigidbody(1, &start, 0.3, props.linear_damping, props.angular_damping);
+        physics.add_collider(handle, &Vec2::ZERO, 0.0, SharedShape::ball(10.0), props);
+        physics.rigid_bodies.get_mut(handle).unwrap().set_linvel(Vector2::new(30.0, -20.0), true);
+        let mut imu = imu.map(|spec| Imu::new(spec, 1));
+        let mut gps = gps.map(|spec| Gps::new(spec, 2));
+        // The filter starts off target and doesn't know the initial push.
+        let mut ekf = Ekf::new(EkfSpec::default(), start + Vec2::new(15.0, -10.0), 0.5);
+        for step in 0..steps {
+            let time = step as f32 * FIX_DT;
+            let thrust = Wrench::new(20000.0 + 15000.0 * (0.7 * time).sin(), 3000.0 * (0.4 * time).cos(), 40000.0 * (0.5 * time).sin());
+            let rb = physics.rigid_bodies.get_mut(handle).unwrap();
+            let force = Vec2::from_angle(rb.rotation().angle()).rotate(Vec2::new(thrust.forward, thrust.lateral)) + push;
+            rb.reset_forces(true);
+            rb.reset_torques(true);
+            rb.add_force(Vector2::new(force.x, force.y), true);
+            rb.add_torque(thrust.torque, true);
+            physics.step_physics();
+            if let Some(imu) = imu.as_mut() {
+                imu.sample(&physics, handle, FIX_DT);
+            }
+            if let Some(gps) = gps.as_mut() {
+                gps.sample(&physics, handle, FIX_DT);
+            }
+            ekf.update(FIX_DT, thrust, &physics, handle, imu.as_ref(), gps.as_ref());
+        }
+        let truth = physics.get_physics_data(handle);
+        let estimate = ekf.get_estimate();
+        let heading = (estimate.rotation - truth.rotation).sin().asin().abs();
+        return (estimate.position.distance(truth.position), estimate.velocity.distance(truth.velocity), heading);
+    }
+
+    #[test]
+    fn converges_to_true_state_under_noisy_sensors() {
+        let (position, velocity, heading) = fly(Some(ImuSpec::default()), Some(GpsSpec::default()), Vec2::ZERO, 900);
+        // It started 18 px, 36 px/s and 0.2 rad off.
+        assert!(position < 3.0, "position error {}", position);
+        assert!(velocity < 5.0, "velocity error {}", velocity);
+        assert!(heading < 0.1, "heading error {}", heading);
+    }
+
+    #[test]
+    fn accelerometer_tracks_unmodelled_force() {
+        let push = Vec2::new(-800.0, 500.0);
+        let (_, with_imu, _) = fly(Some(ImuSpec::default()), Some(GpsSpec::default()), push, 300);
+        let (_, without_imu, _) = fly(None, Some(GpsSpec::default()), push, 300);
+        assert!(with_imu < without_imu / 2.0, "velocity error {} with the accelerometer, {} without", with_imu, without_imu);
+    }
+}
@@ -0,0 +1,115 @@
+#![allow(unused)]
+
+use std::collections::btree_map::Iter;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Something that happened during one physics step, addressed by entity keys.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SimEvent {
+    CollisionStarted { a: u64, b: u64 },
+    CollisionStopped { a: u64, b: u64 },
+    /// Contact force magnitude between two touching colliders of `a` and `b` this step, with the direction of the strongest contact point.
+    ContactForce { a: u64, b: u64, force: f32, direction: Vec2 },
+    /// The entity was removed from the world together with its body, colliders and joints.
+    Despawned { key: u64 },
+    /// The jet reached waypoint `index` of its mission.
+    WaypointReached { jet: u64, index: usize },
+    /// The jet reached the last waypoint of its mission.
+    MissionComplete { jet: u64 },
+    /// The jet's controller took `elapsed` to decide this step, more than `CONTROL_BUDGET`.
+    ControlOverrun { jet: u64, elapsed: Duration },
+    /// The replay being played back ran out of commands at `step`; control is live from here on.
+    ReplayFinished { step: u64 },
+}
+
+impl SimEvent {
+
+    /// The two entities involved, for events that describe a contact between them.
+    pub fn contact_pair(&self) -> Option<(u64, u64)> {
+        match *self {
+            SimEvent::CollisionStarted { a, b } => Some((a, b)),
+            SimEvent::CollisionStopped { a, b } => Some((a, b)),
+            SimEvent::ContactForce { a, b, .. } => Some((a, b)),
+            SimEvent::Despawned { .. } => None,
+            SimEvent::WaypointReached { .. } | SimEvent::MissionComplete { .. } | SimEvent::ControlOverrun { .. } | SimEvent::ReplayFinished { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Contact {
+    /// Number of touching collider pairs between the two entities.
+    pub pairs: u32,
+    /// Contact force magnitude applied during the last step; zero while below `CONTACT_FORCE_THRESHOLD`.
+    pub force: f32,
+}
+
+/// Entities an object is currently touching, keyed by the other entity's key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Contacts {
+    touching: BTreeMap<u64, Contact>,
+}
+
+impl Contacts {
+
+    /// Updates the contact with `other` from an event that involves both entities.
+    pub fn apply(&mut self, event: &SimEvent, other: u64) {
+        match *event {
+            SimEvent::CollisionStarted { .. } => self.begin(other),
+            SimEvent::CollisionStopped { .. } => self.end(other),
+            SimEvent::ContactForce { force, .. } => self.add_force(other, force),
+            // Not a contact event; the simulation calls `forget` on every remaining entity when it despawns one.
+            SimEvent::Despawned { .. } => {},
+            SimEvent::WaypointReached { .. } | SimEvent::MissionComplete { .. } | SimEvent::ControlOverrun { .. } | SimEvent::ReplayFinished { .. } => {},
+        }
+    }
+
+    pub fn begin(&mut self, other: u64) {
+        self.touching.entry(other).or_default().pairs += 1;
+    }
+
+    pub fn end(&mut self, other: u64) {
+        if let Some(contact) = self.touching.get_mut(&other) {
+            contact.pairs = contact.pairs.saturating_sub(1);
+            if contact.pairs == 0 {
+                self.touching.remove(&other);
+            }
+        }
+    }
+
+    /// Drops every contact with `other`, e.g. because it no longer exists.
+    pub fn forget(&mut self, other: u64) {
+        self.touching.remove(&other);
+    }
+
+    pub fn add_force(&mut self, other: u64, force: f32) {
+        if let Some(contact) = self.touching.get_mut(&other) {
+            contact.force += force;
+        }
+    }
+
+    pub fn clear_forces(&mut self) {
+        for contact in self.touching.values_mut() {
+            contact.force = 0.0;
+        }
+    }
+
+    pub fn get(&self, other: u64) -> Option<&Contact> {
+        return self.touching.get(&other);
+    }
+
+    pub fn get_iter(&self) -> Iter<'_, u64, Contact> {
+        return self.touching.iter();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.touching.is_empty();
+    }
+
+    pub fn max_force(&self) -> f32 {
+        return self.touching.values().fold(0.0, |max, contact| max.max(contact.force));
+    }
+}
@@ -0,0 +1,186 @@
+#![allow(unused)]
+
+use std::thread;
+use crate::env::{JetEnv, Task};
+use crate::neural::{Genome, Network};
+use crate::rng::SimRng;
+use crate::scene::SceneDef;
+
+/// Settings of a neuroevolution run.
+#[derive(Clone, Debug)]
+pub struct EvolutionSpec {
+    pub population: usize,
+    /// Best genomes carried over unchanged into the next generation.
+    pub elite: usize,
+    /// Genomes drawn per tournament; the fittest of them becomes a parent.
+    pub tournament: usize,
+    /// Chance that a child has two parents rather than being a copy of one.
+    pub crossover: f32,
+    /// Chance that each weight of a child is mutated.
+    pub mutation_rate: f32,
+    /// Standard deviation of a weight mutation.
+    pub mutation_size: f32,
+    /// Neurons per hidden layer.
+    pub hidden: Vec<usize>,
+    /// Episodes, on seeds `seed`, `seed + 1`, ..., each genome is scored on.
+    pub episodes: u64,
+    pub max_steps: u64,
+    /// Threads genomes are scored on; zero uses every available core. The results don't depend on it.
+    pub threads: usize,
+}
+
+impl Default for EvolutionSpec {
+    fn default() -> Self {
+        Self { population: 50, elite: 4, tournament: 3, crossover: 0.7, mutation_rate: 0.1, mutation_size: 0.2, hidden: vec![16], episodes: 3, max_steps: 600, threads: 0 }
+    }
+}
+
+/// Fitness of one generation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GenerationStats {
+    pub generation: u32,
+    pub best: f32,
+    pub mean: f32,
+}
+
+/// Evolves networks for a `JetEnv` task. Every genome flies the same episodes in its own headless simulation, scored by
+/// its mean return; the next generation keeps the elite and breeds the rest by tournament selection, uniform crossover
+/// and Gaussian mutation. Runs are reproducible from the seed whatever the thread count.
+pub struct Trainer {
+    pub spec: EvolutionSpec,
+    pub task: Task,
+    pub scene: SceneDef,
+    seed: u64,
+    /// Each network with its fitness, once evaluated.
+    population: Vec<(Network, Option<f32>)>,
+    best: Option<Genome>,
+    generation: u32,
+    rng: SimRng,
+}
+
+impl Trainer {
+
+    /// Random first generation, sized to the observations and actions of the scene's first jet.
+    pub fn new(spec: EvolutionSpec, task: Task, scene: SceneDef, seed: u64) -> Self {
+        let mut env = JetEnv::new(task, scene.clone());
+        env.reset(seed);
+        let mut layers = vec![env.observation_space().dim()];
+        layers.extend(spec.hidden.iter().copied());
+        layers.push(env.action_space().dim());
+        let mut rng = SimRng::new(seed);
+        let population = (0..spec.population.max(1)).map(|_| (Network::random(layers.clone(), &mut rng), None)).collect();
+        Self { spec, task, scene, seed, population, best: None, generation: 0, rng }
+    }
+
+    pub fn get_generation(&self) -> u32 {
+        return self.generation;
+    }
+
+    /// Fittest genome found so far.
+    pub fn get_best(&self) -> Option<&Genome> {
+        return self.best.as_ref();
+    }
+
+    /// Mean return of `network` over the training episodes.
+    pub fn evaluate(&self, network: &Network) -> f32 {
+        let mut total = 0.0;
+        for episode in 0..self.spec.episodes {
+            let mut env = JetEnv::new(self.task, self.scene.clone());
+            env.max_steps = self.spec.max_steps;
+            let mut observation = env.reset(self.seed.wrapping_add(episode));
+            let actions = env.action_space();
+            loop {
+                let transition = env.step(&actions.scale(&network.forward(&observation)));
+                total += transition.reward;
+                if transition.done {
+                    break;
+                }
+                observation = transition.observation;
+            }
+        }
+        return total / self.spec.episodes.max(1) as f32;
+    }
+
+    /// Scores the genomes that haven't been yet, spread over `spec.threads` threads, then breeds the next generation.
+    pub fn run_generation(&mut self) -> GenerationStats {
+        let threads = match self.spec.threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        };
+        let pending: Vec<usize> = (0..self.population.len()).filter(|i| self.population[*i].1.is_none()).collect();
+        let chunk = pending.len().div_ceil(threads).max(1);
+        let scores: Vec<(usize, f32)> = thread::scope(|scope| {
+            let this = &*self;
+            let workers: Vec<_> = pending.chunks(chunk).map(|indices| scope.spawn(move || {
+                return indices.iter().map(|i| (*i, this.evaluate(&this.population[*i].0))).collect::<Vec<_>>();
+            })).collect();
+            return workers.into_iter().flat_map(|worker| worker.join().expect("evaluation thread panicked")).collect();
+        });
+        for (i, score) in scores {
+            self.population[i].1 = Some(score);
+        }
+        self.population.sort_by(|a, b| b.1.unwrap_or(f32::MIN).total_cmp(&a.1.unwrap_or(f32::MIN)));
+        let fitness: Vec<f32> = self.population.iter().map(|(_, score)| score.unwrap_or(f32::MIN)).collect();
+        let stats = GenerationStats { generation: self.generation, best: fitness[0], mean: fitness.iter().sum::<f32>() / fitness.len() as f32 };
+        if self.best.as_ref().map(|best| stats.best > best.fitness).unwrap_or(true) {
+            self.best = Some(Genome { task: self.task, network: self.population[0].0.clone(), fitness: stats.best, generation: self.generation });
+        }
+        let mut next: Vec<(Network, Option<f32>)> = self.population.iter().take(self.spec.elite).cloned().collect();
+        while next.len() < self.population.len() {
+            let parent = self.select(&fitness);
+            let mut child = self.population[parent].0.clone();
+            if self.rng.next_f32() < self.spec.crossover {
+                let other = self.select(&fitness);
+                for (i, weight) in child.weights.iter_mut().enumerate() {
+                    if self.rng.next_f32() < 0.5 {
+                        *weight = self.population[other].0.weights[i];
+                    }
+                }
+            }
+            for weight in child.weights.iter_mut() {
+                if self.rng.next_f32() < self.spec.mutation_rate {
+                    *weight += self.spec.mutation_size * self.rng.gaussian();
+                }
+            }
+            next.push((child, None));
+        }
+        self.population = next;
+        self.generation += 1;
+        return stats;
+    }
+
+    /// Tournament selection over the current, scored population; returns the winner's index.
+    fn select(&mut self, fitness: &[f32]) -> usize {
+        let mut winner = self.rng.gen_index(fitness.len());
+        for _ in 1..self.spec.tournament {
+            let challenger = self.rng.gen_index(fitness.len());
+            if fitness[challenger] > fitness[winner] {
+                winner = challenger;
+            }
+        }
+        return winner;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(threads: usize) -> (Vec<GenerationStats>, Genome) {
+        let spec = EvolutionSpec { population: 6, elite: 1, hidden: vec![4], episodes: 1, max_steps: 15, threads, ..EvolutionSpec::default() };
+        let mut trainer = Trainer::new(spec, Task::ReachTarget, SceneDef::default_scene(), 21);
+        let stats = (0..2).map(|_| trainer.run_generation()).collect();
+        return (stats, trainer.get_best().unwrap().clone());
+    }
+
+    #[test]
+    fn same_seed_trains_the_same_on_any_thread_count() {
+        let (stats, best) = run(1);
+        for threads in [2, 4] {
+            let (other_stats, other_best) = run(threads);
+            assert_eq!(stats, other_stats, "{} threads", threads);
+            assert_eq!(best.network, other_best.network, "{} threads", threads);
+            assert_eq!(best.generation, other_best.generation);
+        }
+    }
+}
@@ -1,141 +1,397 @@
 #![allow(unused)]
 
-use std::f32::consts::PI;
+use std::f32::consts::{FRAC_PI_2, PI};
 use crate::consts::*;
 //use crate::sim::*;
 use crate::util::*;
 use crate::physics::*;
+use crate::rng::SimRng;
+use crate::replay::Command;
+use crate::entity::*;
+use crate::mixer::*;
+use crate::autopilot::*;
+use crate::mission::*;
+use crate::sensors::*;
+use crate::estimator::*;
+use crate::controller::*;
+use std::time::{Duration, Instant};
 use macroquad::{color, prelude::*};
-use macroquad::rand::*;
 use rapier2d::geometry::*;
 use rapier2d::na::Point2;
 use rapier2d::na::Vector2;
 use rapier2d::prelude::vector;
 use rapier2d::prelude::{RigidBody, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
 
 
+#[derive(Serialize, Deserialize)]
 pub struct JetEng {
+    /// Mounting point in the jet's body frame.
     pub pos: Vec2,
+    /// Unit thrust direction in the jet's body frame with the nozzle centred; the exhaust leaves the opposite way.
     pub jet_vec: Vec2,
-    pub max_impulse: f32,
-    pub impulse: f32,
+    pub max_thrust: f32,
+    /// Lowest throttle the engine can run at; negative allows reverse thrust, positive is an idle it can't go below.
+    pub min_throttle: f32,
+    /// First-order lag time constants for thrust rising and falling, in seconds.
+    pub spool_up: f32,
+    pub spool_down: f32,
+    /// Throttle the engine was last asked for.
+    pub command: f32,
+    /// Thrust actually produced, lagging behind `command`.
+    pub thrust: f32,
+    /// Largest nozzle deflection either side of `jet_vec`, in radians; zero for a fixed nozzle.
+    pub gimbal_limit: f32,
+    /// How fast the nozzle swings, in radians per second.
+    pub slew_rate: f32,
+    /// Nozzle angle the engine was last asked for.
+    pub gimbal_command: f32,
+    /// Current nozzle angle, slewing towards `gimbal_command`.
+    pub gimbal: f32,
 }
 
 impl JetEng {
     
-    pub fn new(pos: Vec2, jet_vec: Vec2, max_impulse: f32) -> Self {
-        Self{pos, jet_vec, max_impulse, impulse: 0.0}
+    pub fn new(pos: Vec2, jet_vec: Vec2, max_thrust: f32) -> Self {
+        Self{pos, jet_vec, max_thrust, min_throttle: -1.0, spool_up: SPOOL_UP, spool_down: SPOOL_DOWN, command: 0.0, thrust: 0.0, gimbal_limit: 0.0, slew_rate: 0.0, gimbal_command: 0.0, gimbal: 0.0}
     }
 
-    pub fn power(&mut self, thrust: f32) {
-        let thrust = thrust.clamp(-1.0, 1.0);
-        self.impulse = self.max_impulse*thrust;
+    pub fn with_gimbal(mut self, gimbal_limit: f32, slew_rate: f32) -> Self {
+        self.gimbal_limit = gimbal_limit.clamp(0.0, FRAC_PI_2);
+        self.slew_rate = slew_rate;
+        return self;
     }
 
-    pub fn get_impulse(&self) -> Vec2 {
-        return self.jet_vec*self.impulse;
+    pub fn with_spool(mut self, min_throttle: f32, spool_up: f32, spool_down: f32) -> Self {
+        self.min_throttle = min_throttle.clamp(-1.0, 1.0);
+        self.spool_up = spool_up;
+        self.spool_down = spool_down;
+        self.command = self.command.max(self.min_throttle);
+        self.thrust = self.max_thrust*self.command;
+        return self;
     }
 
+    /// Sets the throttle the engine spools towards.
+    pub fn power(&mut self, throttle: f32) {
+        let (lo, hi) = self.throttle_limits();
+        self.command = throttle.clamp(lo, hi);
+    }
+
+    /// Points the nozzle `angle` radians off `jet_vec`, as far as the gimbal allows.
+    pub fn aim(&mut self, angle: f32) {
+        self.gimbal_command = angle.clamp(-self.gimbal_limit, self.gimbal_limit);
+    }
+
+    /// Swings the nozzle towards its commanded angle at no more than `slew_rate`.
+    pub fn slew(&mut self, dt: f32) {
+        let step = self.slew_rate*dt;
+        self.gimbal += (self.gimbal_command - self.gimbal).clamp(-step, step);
+    }
+
+    /// Current thrust direction in the jet's body frame.
+    pub fn get_direction(&self) -> Vec2 {
+        return Vec2::from_angle(self.gimbal).rotate(self.jet_vec);
+    }
+
+    /// Range `power` accepts, as fractions of `max_thrust`.
+    pub fn throttle_limits(&self) -> (f32, f32) {
+        return (self.min_throttle, 1.0);
+    }
+
+    /// Moves the produced thrust towards the commanded one; spooling up and down can take different times.
+    pub fn spool(&mut self, dt: f32) {
+        let target = self.max_thrust*self.command;
+        let tau = match target.abs() > self.thrust.abs() {
+            true => self.spool_up,
+            false => self.spool_down,
+        };
+        if tau <= 0.0 {
+            self.thrust = target;
+        } else {
+            self.thrust += (target - self.thrust)*(1.0 - (-dt/tau).exp());
+        }
+    }
+
+    /// Thrust force in the jet's body frame.
+    pub fn get_thrust(&self) -> Vec2 {
+        return self.get_direction()*self.thrust;
+    }
+
+    /// Draws the nozzle at its current angle and the exhaust plume, rotated into the world by the jet's physics rotation `rot`.
     pub fn draw(&self, x0: f32, y0: f32, rot: f32, color: Color) {
         let pos = Vec2::from_angle(rot).rotate(self.pos);
         draw_circle(pos.x+x0, pos.y+y0, 3.0, color);
-        let mut imp = self.get_impulse();
-        imp = Vec2::from_angle(rot).rotate(imp)*1000.0;
-        draw_line(pos.x+x0, pos.y+y0, pos.x+x0+imp.x, pos.y+y0+imp.y, 2.0, RED);
+        let nozzle = -Vec2::from_angle(rot).rotate(self.get_direction())*NOZZLE_LENGTH;
+        draw_line(pos.x+x0, pos.y+y0, pos.x+x0+nozzle.x, pos.y+y0+nozzle.y, 3.0, color);
+        let exhaust = -Vec2::from_angle(rot).rotate(self.get_thrust())*EXHAUST_SCALE;
+        draw_line(pos.x+x0, pos.y+y0, pos.x+x0+exhaust.x, pos.y+y0+exhaust.y, 2.0, RED);
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Jet {
-    pub key: u64,
-    pub pos: Vec2,
-    pub rot: f32,
+    pub body: Body,
     pub size: f32,
-    pub color: color::Color,
-    pub shape: SharedShape,
-    pub physics_handle: RigidBodyHandle,
-    pub engines: Vec<JetEng>,    
+    pub engines: Vec<JetEng>,
+    /// Where the throttle settles on axes the stick leaves centred.
+    pub neutral: Wrench,
+    /// Flight logic producing the engine commands every tick.
+    #[serde(default)]
+    pub controller: Pilot,
+    /// Gains for the autopilot whenever the jet is switched to one.
+    #[serde(default)]
+    pub autopilot_gains: AutopilotGains,
+    /// Ticks on which the controller took longer than `CONTROL_BUDGET`.
+    #[serde(default)]
+    pub overruns: u32,
+    /// How long the controller took this tick, if that was over budget; reported as a `ControlOverrun` event.
+    #[serde(skip)]
+    overrun: Option<Duration>,
+    /// Waypoints the autopilot is flying, if any.
+    #[serde(default)]
+    pub mission: Option<MissionProgress>,
+    /// Rangefinders and lidars, sampled after every physics step.
+    #[serde(default)]
+    pub sensors: Vec<RangeSensor>,
+    #[serde(default)]
+    pub imu: Option<Imu>,
+    #[serde(default)]
+    pub gps: Option<Gps>,
+    /// State estimate built from the thrust and the IMU and GPS readings.
+    #[serde(default)]
+    pub estimator: Option<Ekf>,
 }
 
 impl Jet {
 
-    pub fn new_dyn_poly(vertices: Vec<Vec2>, position: Vec2, physics: &mut Physics) -> Self {
+    /// Classic two-engine layout: one nozzle on each flank, pushing in opposite directions.
+    pub fn default_engines() -> Vec<JetEng> {
+        return vec![
+            JetEng::new(Vec2::new(-5.0, -10.0), Vec2::new(1.0, 0.0), 3000.0),
+            JetEng::new(Vec2::new(-5.0, 10.0), Vec2::new(-1.0, 0.0), 3000.0),
+        ];
+    }
+
+    pub fn new_dyn_poly(vertices: Vec<Vec2>, position: Vec2, rotation: f32, engines: Vec<JetEng>, props: PhysicsProperities, physics: &mut Physics, rng: &mut SimRng) -> Self {
         let points = vec2_to_point2_collection(&vertices);
         let points2 = points.as_slice();
         let shape = SharedShape::convex_hull(points2).unwrap();
-        let key = gen_range(u64::MIN, u64::MAX);
+        let key = rng.next_u64();
         let pos = position;
-        let rbh = physics.add_dynamic(key, &pos, 0.0, shape.clone(), PhysicsProperities::default());
+        let rbh = physics.add_dynamic(key, &pos, rotation, shape.clone(), props, rng);
         Self {
-            key,
-            pos,
-            //rot: random_rotation(),
-            rot: 0.0,
+            body: Body::new(key, pos, rotation, LIGHTGRAY, shape, rbh),
             size: 5.0,
-            color: LIGHTGRAY,
-            shape: shape,
-            physics_handle: rbh,
-            engines: vec![
-                JetEng::new(Vec2::new(-5.0, -10.0), Vec2::new(1.0, 0.0), 0.5),
-                JetEng::new(Vec2::new(-5.0, 10.0), Vec2::new(-1.0, 0.0), 0.5),
-            ]
+            engines,
+            neutral: Wrench::default(),
+            controller: Pilot::default(),
+            autopilot_gains: AutopilotGains::default(),
+            overruns: 0,
+            overrun: None,
+            mission: None,
+            sensors: vec![],
+            imu: None,
+            gps: None,
+            estimator: None,
         }
     }
 
-    pub fn go(&mut self, thrust: f32) {
-        for eng in self.engines.iter_mut() {
-            eng.power(thrust);
-        }        
+    pub fn mixer(&self) -> Mixer {
+        return Mixer::new(&self.engines);
+    }
+
+    /// Sets every engine's throttle and nozzle so together they come as close as they can to `demand`.
+    pub fn mix(&mut self, demand: Wrench) {
+        let settings = self.mixer().allocate(demand);
+        for (eng, setting) in self.engines.iter_mut().zip(settings) {
+            eng.power(setting.throttle);
+            eng.aim(setting.angle);
+        }
+    }
+
+    pub fn get_autopilot(&self) -> Option<&Autopilot> {
+        return self.controller.controller().autopilot();
+    }
+
+    /// Stick position, if the jet is flown by hand.
+    pub fn get_stick(&self) -> Option<Wrench> {
+        return self.controller.controller().stick();
+    }
+
+    /// Swaps in another controller; it takes over from the next tick.
+    pub fn set_controller(&mut self, controller: Pilot) {
+        self.controller = controller;
+    }
+
+    /// Routes a command: mission and autopilot commands are handled here, switching controllers replaces the current one,
+    /// and anything else goes to the controller, which ignores what it has no use for.
+    pub fn apply_command(&mut self, command: Command) {
+        let rot = self.body.rot;
+        match command {
+            Command::Waypoint(waypoint) => self.add_waypoint(waypoint),
+            Command::Abort => self.abort_mission(),
+            Command::Control(kind) => self.set_controller(Pilot::of_kind(kind, self.autopilot_gains)),
+            Command::Engage(mode) => if let Some(autopilot) = self.controller.controller_mut().autopilot_mut() {
+                autopilot.engage(mode, rot);
+            },
+            Command::Disengage => if let Some(autopilot) = self.controller.controller_mut().autopilot_mut() {
+                autopilot.disengage();
+            },
+            _ => _ = self.controller.controller_mut().command(command),
+        }
+    }
+
+    /// Appends a waypoint to the mission, starting one if there is none.
+    pub fn add_waypoint(&mut self, waypoint: Waypoint) {
+        self.mission.get_or_insert_with(MissionProgress::default).push(waypoint);
+    }
+
+    /// Drops the mission and hands the jet back to the pilot.
+    pub fn abort_mission(&mut self) {
+        if self.mission.take().is_none() {
+            return;
+        }
+        if let Some(autopilot) = self.controller.controller_mut().autopilot_mut() {
+            autopilot.disengage();
+        }
+    }
+
+    /// Samples every sensor against the world as it is after the last physics step.
+    pub fn sense(&mut self, physics: &Physics, dt: f32) {
+        let handle = self.body.physics_handle;
+        for sensor in self.sensors.iter_mut() {
+            sensor.sample(physics, handle);
+        }
+        if let Some(imu) = self.imu.as_mut() {
+            imu.sample(physics, handle, dt);
+        }
+        if let Some(gps) = self.gps.as_mut() {
+            gps.sample(physics, handle, dt);
+        }
+        let thrust = self.get_wrench();
+        if let Some(estimator) = self.estimator.as_mut() {
+            estimator.update(dt, thrust, physics, handle, self.imu.as_ref(), self.gps.as_ref());
+        }
+    }
+
+    /// Time the controller took on the last tick if it overran `CONTROL_BUDGET`; cleared once taken.
+    pub fn take_overrun(&mut self) -> Option<Duration> {
+        return self.overrun.take();
+    }
+
+    /// Body-frame force and torque the engines are producing right now.
+    pub fn get_wrench(&self) -> Wrench {
+        let mut total = Wrench::default();
+        for eng in self.engines.iter() {
+            let force = eng.get_thrust();
+            total.forward += force.x;
+            total.lateral += force.y;
+            total.torque += eng.pos.perp_dot(force);
+        }
+        return total;
+    }
+
+    /// Advances the mission from the jet's state after the last physics step. Only a controller with an autopilot can fly it.
+    pub fn run_mission(&mut self, dt: f32, physics: &Physics) -> Vec<MissionEvent> {
+        let (Some(mission), Some(autopilot)) = (self.mission.as_mut(), self.controller.controller_mut().autopilot_mut()) else {
+            return vec![];
+        };
+        let state = physics.get_physics_data(self.body.physics_handle);
+        return mission.update(&state, autopilot, dt);
     }
 
-    pub fn turn_left(&mut self) {
-        self.engines[0].power(-1.0);
-        self.engines[1].power(1.0);
+    /// Runs the controller on what the jet can see and hands its actuation to the engines. Overrunning `CONTROL_BUDGET`
+    /// is counted and reported but doesn't change the result, so runs stay reproducible.
+    fn run_controller(&mut self, dt: f32, state: PhysicsData) {
+        let observation = Observation {
+            state,
+            estimate: self.estimator.as_ref().map(|estimator| estimator.get_estimate()),
+            ranges: &self.sensors,
+            imu: self.imu.as_ref().and_then(|imu| imu.get_reading()),
+            gps: self.gps.as_ref().and_then(|gps| gps.get_reading()),
+            neutral: self.neutral,
+            engines: &self.engines,
+            budget: CONTROL_BUDGET,
+        };
+        let start = Instant::now();
+        let actuation = self.controller.controller_mut().control(&observation, dt);
+        let elapsed = start.elapsed();
+        self.overrun = (elapsed > CONTROL_BUDGET).then_some(elapsed);
+        if self.overrun.is_some() {
+            self.overruns += 1;
+        }
+        match actuation {
+            Actuation::Throttle(throttle) => {
+                let authority = self.mixer().authority();
+                self.mix(Wrench::new(throttle.forward*authority.forward, throttle.lateral*authority.lateral, throttle.torque*authority.torque));
+            },
+            Actuation::Engines(settings) => {
+                for (eng, setting) in self.engines.iter_mut().zip(settings) {
+                    eng.power(setting.throttle);
+                    eng.aim(setting.angle);
+                }
+            },
+        }
     }
 
-    pub fn turn_right(&mut self) {
-        self.engines[0].power(1.0);
-        self.engines[1].power(-1.0);
+    fn draw_engs(&self, pos: Vec2, rot: f32) {
+        for eng in self.engines.iter() {
+            eng.draw(pos.x, pos.y, rot, SKYBLUE);
+        }
     }
+}
 
-    pub fn draw(&self) {
-        let x0 = self.pos.x;
-        let y0 = self.pos.y;
-        let points = self.shape.as_convex_polygon().unwrap().points().to_vec();
+impl Entity for Jet {
+
+    fn body(&self) -> &Body {
+        return &self.body;
+    }
+
+    fn body_mut(&mut self) -> &mut Body {
+        return &mut self.body;
+    }
+
+    fn draw_at(&self, pos: Vec2, rot: f32) {
+        let x0 = pos.x;
+        let y0 = pos.y;
+        let points = self.body.shape.as_convex_polygon().unwrap().points().to_vec();
         let mut pre_point = points[points.len()-1];
         let mut v0 = Vec2::new(pre_point.x, pre_point.y);
-        v0 = Vec2::from_angle(self.rot).rotate(v0);
+        v0 = Vec2::from_angle(rot).rotate(v0);
         for p in points {
             let mut v1 = Vec2::new(p.x, p.y);
-            v1 = Vec2::from_angle(self.rot).rotate(v1);
-            draw_line(x0+v0.x, y0+v0.y, x0+v1.x, y0+v1.y, 2.0, self.color);
-            v0 = v1.clone();
+            v1 = Vec2::from_angle(rot).rotate(v1);
+            draw_line(x0+v0.x, y0+v0.y, x0+v1.x, y0+v1.y, 2.0, self.body.color);
+            v0 = v1;
         }
         draw_circle(x0, y0, 6.0, RED);
-        self.draw_engs();
-    }    
-
-    fn draw_engs(&self) {
-        for eng in self.engines.iter() {
-            eng.draw(self.pos.x, self.pos.y, self.rot, SKYBLUE);
-        }
+        self.draw_engs(pos, rot);
     }
 
-    pub fn update(&mut self, dt: f32, physics: &mut Physics) {
-        self.update_physics(physics);
+    /// Radius of a circle that encloses the hull.
+    fn get_extent(&self) -> f32 {
+        let sphere = self.body.shape.compute_local_bounding_sphere();
+        return sphere.radius + sphere.center.coords.norm();
     }
 
-    fn update_physics(&mut self, physics: &mut Physics) {
-        let physics_data = physics.get_physics_data(self.physics_handle.clone());
-        let mut spec_impulse0: Vec2 = Vec2::ZERO;
-        let mut spec_impulse1: Vec2 = Vec2::ZERO;
-        spec_impulse0 = self.engines[0].get_impulse();
-        spec_impulse1 = self.engines[1].get_impulse();
-        self.pos = physics_data.position;
-        self.rot = physics_data.rotation;
-        let mut rb = physics.rigid_bodies.get_mut(self.physics_handle.clone()).unwrap();
-        let v0 =Vec2::new(self.engines[0].pos.x, self.engines[0].pos.y).to_owned();
-        rb.apply_impulse_at_point(Vector2::new(spec_impulse0.x, spec_impulse0.y), Point2::new(v0.x, v0.y), true);
-        let v1 =Vec2::new(self.engines[1].pos.x, self.engines[1].pos.y).to_owned();
-        rb.apply_impulse_at_point(Vector2::new(spec_impulse1.x, spec_impulse1.y), Point2::new(v1.x, v1.y), true);
+    /// Ramps the throttle and spools the engines, then replaces last step's engine forces with the new ones, each rotated into world space and applied at its world mounting point.
+    /// Being forces, the solver integrates them over the step, so the result doesn't depend on `FIX_DT`.
+    fn update(&mut self, dt: f32, physics: &mut Physics) {
+        let state = physics.get_physics_data(self.body.physics_handle);
+        self.run_controller(dt, state);
+        for eng in self.engines.iter_mut() {
+            eng.spool(dt);
+            eng.slew(dt);
+        }
+        let mut rb = physics.rigid_bodies.get_mut(self.body.physics_handle).unwrap();
+        rb.reset_forces(true);
+        rb.reset_torques(true);
+        let iso = *rb.position();
+        for eng in self.engines.iter() {
+            let thrust = eng.get_thrust();
+            let force = iso.rotation * Vector2::new(thrust.x, thrust.y);
+            let point = iso * Point2::new(eng.pos.x, eng.pos.y);
+            rb.add_force_at_point(force, point, true);
+        }
     }
 }
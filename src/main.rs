@@ -1,6 +1,7 @@
 #![allow(unused)]
 
 mod sim;
+mod app;
 mod camera;
 mod physics;
 mod consts;
@@ -8,9 +9,38 @@ mod util;
 mod unit;
 mod collector;
 mod jet;
+mod entity;
+mod mixer;
+mod autopilot;
+mod mission;
+mod sensors;
+mod estimator;
+mod controller;
+mod env;
+mod neural;
+mod evolution;
+mod rng;
+mod snapshot;
+mod replay;
+mod events;
+mod scene;
+mod editor;
 
+use std::time::Instant;
 use macroquad::prelude::*;
+use crate::consts::*;
 use crate::sim::*;
+use crate::app::*;
+use crate::replay::*;
+use crate::events::SimEvent;
+use crate::scene::SceneDef;
+use crate::entity::Entity;
+use crate::mission::Mission;
+use crate::env::{JetEnv, Task};
+use crate::evolution::{EvolutionSpec, Trainer};
+use crate::neural::Genome;
+use crate::controller::{Neural, Pilot};
+use crate::rng::SimRng;
 
 fn app_configuration() -> Conf {
     Conf {
@@ -23,15 +53,261 @@ fn app_configuration() -> Conf {
     }
 }
 
-#[macroquad::main(app_configuration)]
-async fn main() {
-    let mut sim = Simulation::new();
-    sim.init();
+/// Returns the value following `flag` on the command line, if the flag is present.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    return args.get(idx + 1).map(|val| val.as_str());
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    return args.iter().any(|arg| arg == flag);
+}
+
+/// The `--seed` value, if given.
+fn seed_arg(args: &[String]) -> Option<u64> {
+    return arg_value(args, "--seed").map(|seed| seed.parse().unwrap_or_else(|_| panic!("--seed expects an integer, got '{}'", seed)));
+}
+
+/// The `--scene` file's path and contents, or no path and the built-in default scene.
+fn scene_arg(args: &[String]) -> (Option<String>, SceneDef) {
+    return match arg_value(args, "--scene") {
+        Some(path) => (Some(path.to_string()), load_scene(path)),
+        None => (None, SceneDef::default_scene()),
+    };
+}
+
+/// Loads the scene file at `path`, stopping with an error naming it if that fails.
+fn load_scene(path: &str) -> SceneDef {
+    return SceneDef::load(path).unwrap_or_else(|err| panic!("can't load '{}': {}", path, err));
+}
+
+/// Returns the number of steps requested with `--headless <steps>`, if any.
+fn headless_steps(args: &[String]) -> Option<u64> {
+    if !has_flag(args, "--headless") {
+        return None;
+    }
+    // The step count is optional, so whatever follows only counts if it is a number: `--headless --seed 5` runs 1000 steps.
+    let steps = arg_value(args, "--headless").and_then(|num| num.parse().ok());
+    return Some(steps.unwrap_or(1000));
+}
+
+/// Builds the world the command line asks for; also returns the scene file it came from, if any.
+fn create_simulation(args: &[String]) -> (Simulation, Option<String>) {
+    if let Some(path) = arg_value(args, "--genome") {
+        return genome_simulation(args, path);
+    }
+    if let Some(path) = arg_value(args, "--load") {
+        let sim = snapshot::load(path).unwrap_or_else(|err| panic!("can't load '{}': {}", path, err));
+        println!("loaded snapshot '{}' at step {}", path, sim.get_step_count());
+        return (sim, None);
+    }
+    let replay = arg_value(args, "--replay").map(|path| {
+        Replay::load(path).unwrap_or_else(|err| panic!("can't load '{}': {}", path, err))
+    });
+    let mut sim = match (&replay, seed_arg(args)) {
+        (Some(replay), _) => Simulation::with_seed(replay.seed),
+        (None, Some(seed)) => Simulation::with_seed(seed),
+        (None, None) => Simulation::new(),
+    };
+    println!("seed: {}", sim.get_seed());
+    let scene_path = match &replay {
+        Some(replay) => replay.scene.clone(),
+        None => arg_value(args, "--scene").map(|path| path.to_string()).or_else(|| std::env::var("DRONE_SCENE").ok()),
+    };
+    match &scene_path {
+        Some(path) => {
+            let scene = load_scene(path);
+            scene.build(&mut sim).unwrap_or_else(|err| panic!("can't build '{}': {}", path, err));
+            println!("scene: {}", path);
+        },
+        None => sim.init(),
+    }
+    let boundary = match (&replay, arg_value(args, "--boundary")) {
+        (Some(replay), _) => Some(replay.boundary),
+        (None, Some(name)) => Some(WorldBoundary::parse(name).unwrap_or_else(|| panic!("--boundary expects open, walled or toroidal, got '{}'", name))),
+        (None, None) => None,
+    };
+    if let Some(boundary) = boundary {
+        sim.set_boundary(boundary);
+    }
+    if let Some(replay) = replay {
+        let stop_at = arg_value(args, "--stop-at").map(|step| step.parse().unwrap_or_else(|_| panic!("--stop-at expects a step number, got '{}'", step)));
+        println!("replaying {} commands", replay.events.len());
+        sim.playback = Some(Playback::new(replay, stop_at));
+    }
+    if let Some(path) = arg_value(args, "--record") {
+        let header = Replay::new(sim.get_seed(), scene_path.clone(), sim.get_boundary());
+        let recorder = Recorder::to_file(header, path).unwrap_or_else(|err| panic!("can't record to '{}': {}", path, err));
+        sim.recorder = Some(recorder);
+    }
+    // A replay already carries the waypoint commands it was recorded with.
+    if let (Some(path), false) = (arg_value(args, "--mission"), sim.is_replaying()) {
+        let mission = Mission::load(path).unwrap_or_else(|err| panic!("can't load '{}': {}", path, err));
+        let jet_key = sim.get_jets().next().map(|jet| jet.key()).unwrap_or_else(|| panic!("no jet to fly '{}'", path));
+        for waypoint in mission.waypoints {
+            sim.command(jet_key, Command::Waypoint(waypoint));
+        }
+        println!("mission: {}", path);
+    }
+    return (sim, scene_path);
+}
+
+/// Sets up an episode of the genome's task, seeded like `--env`, with the first jet flown by the genome's network.
+fn genome_simulation(args: &[String], path: &str) -> (Simulation, Option<String>) {
+    let genome = Genome::load(path).unwrap_or_else(|err| panic!("can't load '{}': {}", path, err));
+    let (scene_path, scene) = scene_arg(args);
+    let seed = seed_arg(args).unwrap_or_else(SimRng::time_seed);
+    let mut env = JetEnv::new(genome.task, scene);
+    env.reset(seed);
+    let (inputs, outputs) = (env.observation_space().dim(), env.action_space().dim());
+    if (inputs, outputs) != (genome.network.get_inputs(), genome.network.get_outputs()) {
+        panic!("'{}' was trained for {} observations and {} actions, this scene has {} and {}", path, genome.network.get_inputs(), genome.network.get_outputs(), inputs, outputs);
+    }
+    let target = env.get_target();
+    let mut sim = env.into_sim().expect("the environment was just reset");
+    let jet_key = sim.get_jets().next().map(|jet| jet.key()).expect("the environment has a jet");
+    if let Some(jet) = sim.get_jet_mut(jet_key) {
+        jet.set_controller(Pilot::Neural(Neural { network: genome.network, target }));
+    }
+    println!("seed: {}", seed);
+    println!("genome: {} ({} task, generation {}, fitness {:.2})", path, genome.task, genome.generation, genome.fitness);
+    return (sim, scene_path);
+}
+
+fn run_headless(args: &[String], steps: u64) {
+    let (mut sim, _) = create_simulation(args);
+    let start = Instant::now();
+    let mut collisions = 0;
+    for _ in 0..steps {
+        sim.step();
+        for event in sim.step_events() {
+            match event {
+                SimEvent::CollisionStarted { .. } => collisions += 1,
+                SimEvent::WaypointReached { jet, index } => println!("step {}: jet {} reached waypoint {}", sim.get_step_count(), jet, index),
+                SimEvent::MissionComplete { jet } => println!("step {}: jet {} completed its mission", sim.get_step_count(), jet),
+                SimEvent::ReplayFinished { step } => println!("replay finished at step {}", step),
+                SimEvent::ControlOverrun { jet, elapsed } if sim.get_jet(*jet).map(|jet| jet.overruns == 1).unwrap_or(false) => {
+                    println!("step {}: jet {} controller took {:?}, over its {:?} budget", sim.get_step_count(), jet, elapsed, CONTROL_BUDGET);
+                },
+                _ => {},
+            }
+        }
+        if let Some(err) = sim.recorder.as_mut().and_then(|recorder| recorder.take_error()) {
+            println!("replay recording stopped: {}", err);
+        }
+    }
+    let elapsed = start.elapsed();
+    println!("headless: {} steps in {:.3}s ({} bodies, {} collisions)", steps, elapsed.as_secs_f32(), sim.physics.get_physics_obj_num(), collisions);
+    for jet in sim.get_jets() {
+        let body = jet.body();
+        println!("jet {}: pos=({:.2}, {:.2}) rot={:.3} contacts={}", body.key, body.pos.x, body.pos.y, body.rot, body.contacts.get_iter().count());
+        if jet.overruns > 0 {
+            println!("  controller overran its budget on {} steps", jet.overruns);
+        }
+        if let Some(estimator) = jet.estimator.as_ref() {
+            let estimate = estimator.get_estimate();
+            let sigma = estimator.get_position_covariance().trace().sqrt();
+            println!("  estimate: pos=({:.2}, {:.2}) rot={:.3} error={:.2} sigma={:.2}", estimate.position.x, estimate.position.y, estimate.rotation, estimate.position.distance(body.pos), sigma);
+        }
+    }
+    if let Some(path) = arg_value(args, "--save") {
+        snapshot::save(&sim, path).unwrap_or_else(|err| panic!("can't save '{}': {}", path, err));
+        println!("saved snapshot '{}' at step {}", path, sim.get_step_count());
+    }
+}
+
+/// Runs `--episodes` episodes of the `--env` task with uniformly random actions and prints each one's return.
+fn run_env(args: &[String], task_name: &str) {
+    let task = Task::parse(task_name).unwrap_or_else(|| panic!("--env expects reach, hover or avoid, got '{}'", task_name));
+    let (_, scene) = scene_arg(args);
+    let episodes: u64 = arg_value(args, "--episodes").map(|n| n.parse().unwrap_or_else(|_| panic!("--episodes expects a number, got '{}'", n))).unwrap_or(1);
+    let seed = seed_arg(args).unwrap_or_else(SimRng::time_seed);
+    let mut env = JetEnv::new(task, scene);
+    let mut rng = SimRng::new(seed);
+    for episode in 0..episodes {
+        env.reset(seed.wrapping_add(episode));
+        let action_space = env.action_space();
+        println!("episode {}: {} observations, {} actions", episode, env.observation_space().dim(), action_space.dim());
+        let mut total = 0.0;
+        loop {
+            let transition = env.step(&action_space.sample(&mut rng));
+            total += transition.reward;
+            if transition.done {
+                println!("episode {}: return {:.2} after {} steps (distance {:.1}, collisions {}, truncated {})", episode, total, transition.info.step, transition.info.distance, transition.info.collisions, transition.info.truncated);
+                break;
+            }
+        }
+    }
+}
+
+/// Evolves networks for the `--train` task and keeps the best one in `--genome`, `genome.ron` by default.
+fn run_training(args: &[String], task_name: &str) {
+    let task = Task::parse(task_name).unwrap_or_else(|| panic!("--train expects reach, hover or avoid, got '{}'", task_name));
+    let (_, scene) = scene_arg(args);
+    let number = |flag: &str, default: u64| -> u64 {
+        return arg_value(args, flag).map(|n| n.parse().unwrap_or_else(|_| panic!("{} expects a number, got '{}'", flag, n))).unwrap_or(default);
+    };
+    let seed = seed_arg(args).unwrap_or_else(SimRng::time_seed);
+    let mut spec = EvolutionSpec::default();
+    spec.population = number("--population", spec.population as u64) as usize;
+    spec.episodes = number("--episodes", spec.episodes);
+    spec.max_steps = number("--max-steps", spec.max_steps);
+    spec.threads = number("--threads", spec.threads as u64) as usize;
+    let generations = number("--generations", 50);
+    let path = arg_value(args, "--genome").unwrap_or("genome.ron");
+    println!("seed: {}", seed);
+    let mut trainer = Trainer::new(spec, task, scene, seed);
+    for _ in 0..generations {
+        let stats = trainer.run_generation();
+        println!("generation {}: best {:.2} mean {:.2}", stats.generation, stats.best, stats.mean);
+        let Some(best) = trainer.get_best() else {
+            continue;
+        };
+        if best.generation == stats.generation {
+            best.save(path).unwrap_or_else(|err| panic!("can't save '{}': {}", path, err));
+            println!("saved genome '{}'", path);
+        }
+    }
+}
+
+async fn run_window(args: Vec<String>) {
+    let (sim, scene_path) = create_simulation(&args);
+    let mut app = App::new(sim, scene_path);
     loop {
-        sim.input();
-        sim.update();
-        sim.draw();
-        sim.step_physics();
+        app.input();
+        app.update();
+        app.draw();
         next_frame().await;
     }
-}
\ No newline at end of file
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(task) = arg_value(&args, "--env") {
+        return run_env(&args, task);
+    }
+    if let Some(task) = arg_value(&args, "--train") {
+        return run_training(&args, task);
+    }
+    match headless_steps(&args) {
+        Some(steps) => run_headless(&args, steps),
+        None => macroquad::Window::from_config(app_configuration(), run_window(args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        return line.split_whitespace().map(String::from).collect();
+    }
+
+    #[test]
+    fn headless_step_count_is_optional() {
+        assert_eq!(headless_steps(&args("drone --headless 250 --seed 5")), Some(250));
+        assert_eq!(headless_steps(&args("drone --headless --seed 5")), Some(1000));
+        assert_eq!(headless_steps(&args("drone --seed 5 --headless")), Some(1000));
+        assert_eq!(headless_steps(&args("drone --seed 5")), None);
+    }
+}
@@ -0,0 +1,213 @@
+#![allow(unused)]
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::autopilot::*;
+use crate::physics::PhysicsData;
+use crate::util::angle_diff;
+
+/// Acceptance radius for waypoints that don't set their own, in px.
+pub const WAYPOINT_RADIUS: f32 = 20.0;
+/// How close the heading has to be, in radians, before a waypoint with a heading counts as reached.
+const HEADING_TOLERANCE: f32 = 0.2;
+
+/// One stop on a mission.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub pos: Vec2,
+    /// Defaults to `WAYPOINT_RADIUS`.
+    #[serde(default)]
+    pub radius: Option<f32>,
+    /// Heading to settle on before the waypoint counts as reached.
+    #[serde(default)]
+    pub heading: Option<f32>,
+    /// Seconds to stay inside the radius before moving on.
+    #[serde(default)]
+    pub loiter: f32,
+    /// Speed limit on the leg towards this waypoint, in px/s.
+    #[serde(default)]
+    pub max_speed: Option<f32>,
+}
+
+impl fmt::Display for Waypoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional = |value: Option<f32>| value.map(|v| v.to_string()).unwrap_or("-".to_string());
+        write!(f, "{} {} {} {} {} {}", self.pos.x, self.pos.y, optional(self.radius), self.loiter, optional(self.heading), optional(self.max_speed))
+    }
+}
+
+impl Waypoint {
+
+    pub fn at(pos: Vec2) -> Self {
+        Self { pos, radius: None, heading: None, loiter: 0.0, max_speed: None }
+    }
+
+    /// Reads `x y radius loiter heading max_speed`, with `-` for unset optional values; everything after `y` may be left out.
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let value = |i: usize| -> Result<Option<f32>, String> {
+            match words.get(i) {
+                None | Some(&"-") => Ok(None),
+                Some(word) => word.parse().map(Some).map_err(|_| format!("bad waypoint value '{}'", word)),
+            }
+        };
+        if words.len() < 2 || words.len() > 6 {
+            return Err(format!("expected 'x y [radius loiter heading max_speed]', got '{}'", words.join(" ")));
+        }
+        let (Some(x), Some(y)) = (value(0)?, value(1)?) else {
+            return Err("waypoint needs a position".to_string());
+        };
+        return Ok(Self { pos: Vec2::new(x, y), radius: value(2)?, loiter: value(3)?.unwrap_or(0.0), heading: value(4)?, max_speed: value(5)? });
+    }
+
+    pub fn get_radius(&self) -> f32 {
+        return self.radius.unwrap_or(WAYPOINT_RADIUS);
+    }
+}
+
+#[derive(Debug)]
+pub enum MissionError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for MissionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MissionError::Io(err) => write!(f, "mission i/o error: {}", err),
+            MissionError::Parse(msg) => write!(f, "malformed mission: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for MissionError {
+    fn from(err: std::io::Error) -> Self {
+        return MissionError::Io(err);
+    }
+}
+
+/// Ordered waypoints for one jet, stored as RON.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Mission {
+    pub waypoints: Vec<Waypoint>,
+}
+
+impl Mission {
+
+    pub fn parse(text: &str) -> Result<Self, MissionError> {
+        return ron::from_str(text).map_err(|err| MissionError::Parse(err.to_string()));
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MissionError> {
+        let text = fs::read_to_string(path)?;
+        return Self::parse(&text);
+    }
+}
+
+/// Something the mission executor wants reported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissionEvent {
+    Reached(usize),
+    Complete,
+}
+
+/// A mission being flown: which waypoint is active and how long the jet has loitered there.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MissionProgress {
+    pub mission: Mission,
+    index: usize,
+    loitered: f32,
+}
+
+impl MissionProgress {
+
+    pub fn new(mission: Mission) -> Self {
+        Self { mission, index: 0, loitered: 0.0 }
+    }
+
+    /// Appends a waypoint; a finished mission picks up again from it.
+    pub fn push(&mut self, waypoint: Waypoint) {
+        self.mission.waypoints.push(waypoint);
+    }
+
+    pub fn get_index(&self) -> usize {
+        return self.index;
+    }
+
+    pub fn current(&self) -> Option<&Waypoint> {
+        return self.mission.waypoints.get(self.index);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.index >= self.mission.waypoints.len();
+    }
+
+    /// Points the autopilot at the active waypoint and advances once it has been reached, settled on its
+    /// heading and loitered long enough. Call once per step with the jet's fresh physics state.
+    pub fn update(&mut self, state: &PhysicsData, autopilot: &mut Autopilot, dt: f32) -> Vec<MissionEvent> {
+        let mut events = vec![];
+        let Some(waypoint) = self.current().copied() else {
+            return events;
+        };
+        let inside = state.position.distance(waypoint.pos) <= waypoint.get_radius();
+        let mode = match (inside, waypoint.heading) {
+            (true, Some(heading)) => AutopilotMode::Pose { target: waypoint.pos, heading },
+            _ => AutopilotMode::Position(waypoint.pos),
+        };
+        autopilot.steer(mode, state.rotation);
+        autopilot.speed_limit = waypoint.max_speed;
+        let settled = waypoint.heading.map(|heading| angle_diff(state.rotation, heading).abs() <= HEADING_TOLERANCE).unwrap_or(true);
+        if !(inside && settled) {
+            return events;
+        }
+        self.loitered += dt;
+        if self.loitered < waypoint.loiter {
+            return events;
+        }
+        events.push(MissionEvent::Reached(self.index));
+        self.index += 1;
+        self.loitered = 0.0;
+        if self.is_finished() {
+            // Keep holding the last waypoint, and its heading if it has one, rather than drifting off.
+            autopilot.speed_limit = None;
+            let hold = match waypoint.heading {
+                Some(heading) => AutopilotMode::Pose { target: waypoint.pos, heading },
+                None => AutopilotMode::Position(waypoint.pos),
+            };
+            autopilot.steer(hold, state.rotation);
+            events.push(MissionEvent::Complete);
+        }
+        return events;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_at(position: Vec2, rotation: f32) -> PhysicsData {
+        return PhysicsData { position, rotation, mass: 1.0, kin_eng: None, force: None, velocity: Vec2::ZERO, spin: 0.0 };
+    }
+
+    #[test]
+    fn finished_mission_holds_last_heading() {
+        let waypoint = Waypoint { heading: Some(0.5), ..Waypoint::at(Vec2::new(100.0, 100.0)) };
+        let mut progress = MissionProgress::new(Mission { waypoints: vec![waypoint] });
+        let mut autopilot = Autopilot::default();
+        let events = progress.update(&state_at(Vec2::new(101.0, 100.0), 0.5), &mut autopilot, 0.1);
+        assert_eq!(events, vec![MissionEvent::Reached(0), MissionEvent::Complete]);
+        assert_eq!(autopilot.mode, Some(AutopilotMode::Pose { target: waypoint.pos, heading: 0.5 }));
+    }
+
+    #[test]
+    fn finished_mission_without_heading_holds_position() {
+        let waypoint = Waypoint::at(Vec2::new(100.0, 100.0));
+        let mut progress = MissionProgress::new(Mission { waypoints: vec![waypoint] });
+        let mut autopilot = Autopilot::default();
+        progress.update(&state_at(Vec2::new(101.0, 100.0), 2.0), &mut autopilot, 0.1);
+        assert!(progress.is_finished());
+        assert_eq!(autopilot.mode, Some(AutopilotMode::Position(waypoint.pos)));
+    }
+}
@@ -0,0 +1,239 @@
+#![allow(unused)]
+
+use macroquad::prelude::*;
+use rapier2d::na::{Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+use crate::jet::JetEng;
+
+/// Force and torque in the jet's body frame: `forward` along local +x, `lateral` along local +y.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Wrench {
+    pub forward: f32,
+    pub lateral: f32,
+    pub torque: f32,
+}
+
+impl Wrench {
+
+    pub fn new(forward: f32, lateral: f32, torque: f32) -> Self {
+        Self { forward, lateral, torque }
+    }
+
+    fn to_vector(self) -> Vector3<f32> {
+        return Vector3::new(self.forward, self.lateral, self.torque);
+    }
+
+    fn from_vector(v: Vector3<f32>) -> Self {
+        return Self::new(v.x, v.y, v.z);
+    }
+}
+
+/// What the mixer asks of one engine: a throttle and, for gimballed engines, a nozzle angle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineSetting {
+    pub throttle: f32,
+    pub angle: f32,
+}
+
+/// Control allocation: maps a desired body-frame wrench onto per-engine throttles and nozzle angles within each engine's limits.
+/// A gimballed engine is split into two virtual actuators, one along its axis and one across it, recombined after solving.
+pub struct Mixer {
+    columns: Vec<Vector3<f32>>,
+    limits: Vec<(f32, f32)>,
+    /// Per engine: its along-axis column, its cross-axis column if gimballed, and the gimbal limit.
+    slots: Vec<(usize, Option<usize>, f32)>,
+    /// Per engine: mounting point, undeflected direction and max thrust.
+    geometry: Vec<(Vec2, Vec2, f32)>,
+}
+
+impl Mixer {
+
+    pub fn new(engines: &[JetEng]) -> Self {
+        let mut mixer = Self { columns: vec![], limits: vec![], slots: vec![], geometry: vec![] };
+        for eng in engines.iter() {
+            let along = mixer.push_column(eng.pos, eng.jet_vec * eng.max_thrust, eng.throttle_limits());
+            let across = match eng.gimbal_limit > 0.0 {
+                true => {
+                    let reach = eng.gimbal_limit.sin();
+                    Some(mixer.push_column(eng.pos, eng.jet_vec.perp() * eng.max_thrust, (-reach, reach)))
+                },
+                false => None,
+            };
+            mixer.slots.push((along, across, eng.gimbal_limit));
+            mixer.geometry.push((eng.pos, eng.jet_vec, eng.max_thrust));
+        }
+        return mixer;
+    }
+
+    fn push_column(&mut self, pos: Vec2, force: Vec2, limits: (f32, f32)) -> usize {
+        self.columns.push(Vector3::new(force.x, force.y, pos.perp_dot(force)));
+        self.limits.push(limits);
+        return self.columns.len() - 1;
+    }
+
+    /// Wrench produced by the given engine settings.
+    pub fn wrench(&self, settings: &[EngineSetting]) -> Wrench {
+        let mut total = Wrench::default();
+        for ((pos, dir, max_thrust), setting) in self.geometry.iter().zip(settings) {
+            let force = Vec2::from_angle(setting.angle).rotate(*dir) * *max_thrust * setting.throttle;
+            total.forward += force.x;
+            total.lateral += force.y;
+            total.torque += pos.perp_dot(force);
+        }
+        return total;
+    }
+
+    /// Upper bound on what the engines can deliver along each axis on their own.
+    pub fn authority(&self) -> Wrench {
+        let total = self.columns.iter().zip(self.limits.iter()).fold(Vector3::zeros(), |acc, (col, (lo, hi))| acc + col.abs() * lo.abs().max(hi.abs()));
+        return Wrench::from_vector(total);
+    }
+
+    /// Engine settings for `demand`. Gimballed engines turn their solved along/across pair into a thrust and a deflection,
+    /// both clamped to what the engine can do.
+    pub fn allocate(&self, demand: Wrench) -> Vec<EngineSetting> {
+        let solution = self.solve(demand);
+        return self.slots.iter().map(|(along, across, limit)| {
+            let a = solution[*along];
+            let Some(across) = across else {
+                return EngineSetting { throttle: a, angle: 0.0 };
+            };
+            let p = solution[*across];
+            let sign = if a < 0.0 { -1.0 } else { 1.0 };
+            let angle = (p * sign).atan2(a * sign).clamp(-limit, *limit);
+            let (lo, hi) = self.limits[*along];
+            return EngineSetting { throttle: (sign * a.hypot(p)).clamp(lo, hi), angle };
+        }).collect();
+    }
+
+    /// Least-squares actuator values for `demand`, redistributing around saturated actuators.
+    /// Actuators that hit a limit are pinned there and the rest re-solve for what is left, so an unreachable demand degrades gracefully.
+    fn solve(&self, demand: Wrench) -> Vec<f32> {
+        let n = self.columns.len();
+        let mut throttles = vec![0.0; n];
+        let mut free = vec![true; n];
+        for _ in 0..n {
+            let mut residual = demand.to_vector();
+            let mut gram = Matrix3::zeros();
+            for i in 0..n {
+                match free[i] {
+                    true => gram += self.columns[i] * self.columns[i].transpose(),
+                    false => residual -= self.columns[i] * throttles[i],
+                }
+            }
+            // A little damping keeps axes no engine can act on (e.g. lateral with two inline engines) from blowing up the inverse.
+            let damping = 1e-6 * gram.trace().max(1e-6);
+            let Some(inverse) = (gram + Matrix3::identity() * damping).try_inverse() else {
+                break;
+            };
+            let lambda = inverse * residual;
+            let mut saturated = false;
+            for i in 0..n {
+                if !free[i] {
+                    continue;
+                }
+                let (lo, hi) = self.limits[i];
+                let t = self.columns[i].dot(&lambda);
+                throttles[i] = t.clamp(lo, hi);
+                if t < lo || t > hi {
+                    free[i] = false;
+                    saturated = true;
+                }
+            }
+            if !saturated {
+                break;
+            }
+        }
+        self.polish(demand, &mut throttles);
+        return throttles;
+    }
+
+    /// Pinning saturated actuators is greedy and can strand an axis, e.g. two idling engines that could still
+    /// make torque differentially. A few sweeps of box-constrained coordinate descent finish the job.
+    fn polish(&self, demand: Wrench, values: &mut [f32]) {
+        let target = demand.to_vector();
+        let mut residual = self.columns.iter().zip(values.iter()).fold(target, |acc, (col, v)| acc - col * *v);
+        for _ in 0..POLISH_SWEEPS {
+            for (i, col) in self.columns.iter().enumerate() {
+                let norm = col.norm_squared();
+                if norm == 0.0 {
+                    continue;
+                }
+                let (lo, hi) = self.limits[i];
+                let value = (values[i] + col.dot(&residual) / norm).clamp(lo, hi);
+                residual -= col * (value - values[i]);
+                values[i] = value;
+            }
+        }
+    }
+}
+
+/// Coordinate descent sweeps run after the pseudo-inverse solve.
+const POLISH_SWEEPS: usize = 8;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two rear main engines, one of them gimballed, and a pair of side thrusters that only push outwards.
+    fn engines() -> Vec<JetEng> {
+        return vec![
+            JetEng::new(Vec2::new(-10.0, -6.0), Vec2::X, 400.0).with_spool(0.1, 0.0, 0.0),
+            JetEng::new(Vec2::new(-10.0, 6.0), Vec2::X, 400.0).with_spool(0.1, 0.0, 0.0).with_gimbal(0.4, 2.0),
+            JetEng::new(Vec2::new(8.0, -5.0), Vec2::Y, 150.0),
+            JetEng::new(Vec2::new(-8.0, 5.0), -Vec2::Y, 150.0).with_spool(0.0, 0.0, 0.0),
+        ];
+    }
+
+    fn assert_close(a: Wrench, b: Wrench) {
+        let tolerance = 1e-3 * (1.0 + b.forward.abs() + b.lateral.abs() + b.torque.abs());
+        let diff = (a.forward - b.forward).abs() + (a.lateral - b.lateral).abs() + (a.torque - b.torque).abs();
+        assert!(diff < tolerance, "got {:?}, wanted {:?}", a, b);
+    }
+
+    #[test]
+    fn achievable_wrench_is_reproduced() {
+        let engines = engines();
+        let mixer = Mixer::new(&engines);
+        let reachable = [
+            [0.5, 0.5, 0.0, 0.0, 0.0],
+            [0.8, 0.3, 0.2, 0.4, 0.0],
+            [0.2, 0.9, -0.2, 0.0, 0.6],
+        ];
+        for [left, right, angle, side_a, side_b] in reachable {
+            let settings = [
+                EngineSetting { throttle: left, angle: 0.0 },
+                EngineSetting { throttle: right, angle },
+                EngineSetting { throttle: side_a, angle: 0.0 },
+                EngineSetting { throttle: side_b, angle: 0.0 },
+            ];
+            let demand = mixer.wrench(&settings);
+            assert_close(mixer.wrench(&mixer.allocate(demand)), demand);
+        }
+    }
+
+    #[test]
+    fn saturated_demand_stays_within_limits() {
+        let engines = engines();
+        let mixer = Mixer::new(&engines);
+        let authority = mixer.authority();
+        let demands = [
+            Wrench::new(10.0 * authority.forward, 0.0, 0.0),
+            Wrench::new(-10.0 * authority.forward, 0.0, 0.0),
+            Wrench::new(authority.forward, -5.0 * authority.lateral, 5.0 * authority.torque),
+        ];
+        for demand in demands {
+            let settings = mixer.allocate(demand);
+            for (eng, setting) in engines.iter().zip(settings.iter()) {
+                let (lo, hi) = eng.throttle_limits();
+                assert!(setting.throttle >= lo && setting.throttle <= hi, "throttle {} outside {:?}", setting.throttle, (lo, hi));
+                assert!(setting.angle.abs() <= eng.gimbal_limit, "angle {} beyond {}", setting.angle, eng.gimbal_limit);
+            }
+        }
+        // Far past what they can do, the main engines pin to full throttle going forward and to idle going back.
+        let ahead = mixer.allocate(demands[0]);
+        assert_eq!((ahead[0].throttle, ahead[1].throttle), (1.0, 1.0));
+        let astern = mixer.allocate(demands[1]);
+        assert_eq!((astern[0].throttle, astern[1].throttle), (0.1, 0.1));
+    }
+}
@@ -0,0 +1,155 @@
+#![allow(unused)]
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::env::Task;
+use crate::rng::SimRng;
+
+/// Fully connected feed-forward network with tanh on every layer, so each output lies in -1..1.
+/// Weights are one flat vector, layer after layer, each neuron's inputs followed by its bias; that vector is the genome.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Network {
+    /// Neurons per layer, inputs first and outputs last.
+    pub layers: Vec<usize>,
+    pub weights: Vec<f32>,
+}
+
+impl Network {
+
+    /// Number of weights, biases included, a network with `layers` needs.
+    pub fn weight_count(layers: &[usize]) -> usize {
+        return layers.windows(2).map(|pair| (pair[0] + 1) * pair[1]).sum();
+    }
+
+    /// Gaussian weights scaled by each layer's fan-in, so the first generation neither saturates nor sits at zero.
+    pub fn random(layers: Vec<usize>, rng: &mut SimRng) -> Self {
+        let mut weights = Vec::with_capacity(Self::weight_count(&layers));
+        for pair in layers.windows(2) {
+            let scale = 1.0 / (pair[0] as f32 + 1.0).sqrt();
+            for _ in 0..(pair[0] + 1) * pair[1] {
+                weights.push(scale * rng.gaussian());
+            }
+        }
+        Self { layers, weights }
+    }
+
+    pub fn get_inputs(&self) -> usize {
+        return self.layers.first().copied().unwrap_or(0);
+    }
+
+    pub fn get_outputs(&self) -> usize {
+        return self.layers.last().copied().unwrap_or(0);
+    }
+
+    /// Runs `input` through the network; missing inputs count as zero and extra ones are ignored.
+    pub fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let mut values: Vec<f32> = (0..self.get_inputs()).map(|i| input.get(i).copied().unwrap_or(0.0)).collect();
+        let mut w = 0;
+        for pair in self.layers.windows(2) {
+            let mut next = Vec::with_capacity(pair[1]);
+            for _ in 0..pair[1] {
+                let weights = &self.weights[w..w + pair[0] + 1];
+                let sum: f32 = weights[pair[0]] + weights.iter().zip(values.iter()).map(|(w, v)| w * v).sum::<f32>();
+                next.push(sum.tanh());
+                w += pair[0] + 1;
+            }
+            values = next;
+        }
+        return values;
+    }
+}
+
+#[derive(Debug)]
+pub enum GenomeError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for GenomeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenomeError::Io(err) => write!(f, "genome i/o error: {}", err),
+            GenomeError::Parse(msg) => write!(f, "malformed genome: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for GenomeError {
+    fn from(err: std::io::Error) -> Self {
+        return GenomeError::Io(err);
+    }
+}
+
+/// A trained network with the task it was scored on, stored as RON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Genome {
+    pub task: Task,
+    pub network: Network,
+    /// Mean episode return over the training seeds.
+    pub fitness: f32,
+    pub generation: u32,
+}
+
+impl Genome {
+
+    pub fn parse(text: &str) -> Result<Self, GenomeError> {
+        let genome: Self = ron::from_str(text).map_err(|err| GenomeError::Parse(err.to_string()))?;
+        if genome.network.weights.len() != Network::weight_count(&genome.network.layers) {
+            return Err(GenomeError::Parse(format!("{} weights don't fit layers {:?}", genome.network.weights.len(), genome.network.layers)));
+        }
+        return Ok(genome);
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GenomeError> {
+        let text = fs::read_to_string(path)?;
+        return Self::parse(&text);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GenomeError> {
+        let config = ron::ser::PrettyConfig::new().struct_names(false);
+        let text = ron::ser::to_string_pretty(self, config).map_err(|err| GenomeError::Parse(err.to_string()))?;
+        fs::write(path, text)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_uses_exactly_weight_count_weights() {
+        let layers = vec![2, 2, 1];
+        assert_eq!(Network::weight_count(&layers), 9);
+        let weights: Vec<f32> = (0..9).map(|i| 0.1 * i as f32 - 0.4).collect();
+        let network = Network { layers, weights: weights.clone() };
+        let input = [0.5, -1.5];
+        // Each neuron's input weights, then its bias, neuron after neuron and layer after layer.
+        let hidden: Vec<f32> = (0..2).map(|j| (weights[3 * j] * input[0] + weights[3 * j + 1] * input[1] + weights[3 * j + 2]).tanh()).collect();
+        let output = (weights[6] * hidden[0] + weights[7] * hidden[1] + weights[8]).tanh();
+        let result = network.forward(&input);
+        assert_eq!(result.len(), 1);
+        assert!((result[0] - output).abs() < 1e-6, "{} != {}", result[0], output);
+    }
+
+    #[test]
+    fn random_network_has_weight_count_weights() {
+        let layers = vec![7, 16, 8, 3];
+        let network = Network::random(layers.clone(), &mut SimRng::new(4));
+        assert_eq!(network.weights.len(), Network::weight_count(&layers));
+        assert_eq!(network.forward(&[0.0; 7]).len(), 3);
+    }
+
+    #[test]
+    fn genome_with_wrong_weight_count_is_rejected() {
+        let network = Network::random(vec![3, 4, 2], &mut SimRng::new(1));
+        let mut genome = Genome { task: Task::Hover, network, fitness: 1.5, generation: 2 };
+        let text = ron::to_string(&genome).unwrap();
+        assert_eq!(Genome::parse(&text).unwrap().network, genome.network);
+        genome.network.weights.pop();
+        let text = ron::to_string(&genome).unwrap();
+        assert!(matches!(Genome::parse(&text), Err(GenomeError::Parse(_))));
+    }
+}
@@ -1,51 +1,219 @@
 #![allow(unused)]
 
 use crate::consts::*;
+use crate::rng::SimRng;
+use crate::events::SimEvent;
 use crate::util::*;
 use macroquad::prelude::*;
 use rapier2d::na::Isometry2;
 use rapier2d::na::{Point2, Vector2};
 use rapier2d::prelude::*;
 use std::collections::HashSet;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
+/// Named collision layer; every collider belongs to exactly one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layer {
+    Jets,
+    Units,
+    Terrain,
+    Projectiles,
+    Sensors,
+    Debris,
+}
+
+impl Layer {
+
+    pub const ALL: [Layer; 6] = [Layer::Jets, Layer::Units, Layer::Terrain, Layer::Projectiles, Layer::Sensors, Layer::Debris];
+
+    pub fn index(self) -> usize {
+        return self as usize;
+    }
+
+    pub fn from_index(idx: usize) -> Option<Layer> {
+        return Self::ALL.get(idx).copied();
+    }
+
+    pub fn group(self) -> Group {
+        return Group::from_bits_truncate(1 << self.index());
+    }
+}
+
+/// Set of layers, e.g. the layers a material refuses to touch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerMask(pub u32);
+
+impl LayerMask {
+
+    pub const NONE: LayerMask = LayerMask(0);
+
+    pub fn of(layers: &[Layer]) -> Self {
+        return Self(layers.iter().fold(0, |bits, layer| bits | layer.group().bits()));
+    }
+
+    pub fn contains(self, layer: Layer) -> bool {
+        return self.0 & layer.group().bits() != 0;
+    }
+}
+
+/// Which layers touch which. Symmetric; contacts are only generated when both sides accept each other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InteractionMatrix {
+    masks: [u32; Layer::ALL.len()],
+}
+
+impl Default for InteractionMatrix {
+
+    fn default() -> Self {
+        let mut matrix = Self { masks: [u32::MAX; Layer::ALL.len()] };
+        matrix.set(Layer::Debris, Layer::Debris, false);
+        return matrix;
+    }
+}
+
+impl InteractionMatrix {
+
+    pub fn set(&mut self, a: Layer, b: Layer, interact: bool) {
+        if interact {
+            self.masks[a.index()] |= b.group().bits();
+            self.masks[b.index()] |= a.group().bits();
+        } else {
+            self.masks[a.index()] &= !b.group().bits();
+            self.masks[b.index()] &= !a.group().bits();
+        }
+    }
+
+    pub fn interacts(&self, a: Layer, b: Layer) -> bool {
+        return self.masks[a.index()] & b.group().bits() != 0;
+    }
+
+    /// Collision groups for a collider on `layer` that additionally ignores the layers in `ignore`.
+    pub fn collision_groups(&self, layer: Layer, ignore: LayerMask) -> InteractionGroups {
+        let filter = Group::from_bits_truncate(self.masks[layer.index()] & !ignore.0);
+        return InteractionGroups::new(layer.group(), filter);
+    }
+
+    /// Solver groups: same as collision groups, except sensors only report and never push back.
+    pub fn solver_groups(&self, layer: Layer, ignore: LayerMask) -> InteractionGroups {
+        if layer == Layer::Sensors {
+            return InteractionGroups::none();
+        }
+        let mut groups = self.collision_groups(layer, ignore);
+        groups.filter &= !Layer::Sensors.group();
+        return groups;
+    }
+}
+
+#[derive(Clone)]
 pub struct PhysicsProperities {
     pub friction: f32,
     pub restitution: f32,
     pub density: f32,
     pub linear_damping: f32,
     pub angular_damping: f32,
+    pub layer: Layer,
+    pub ignore: LayerMask,
 }
 
 impl Default for PhysicsProperities {
     
     fn default() -> Self {
-        Self { friction: 0.5, restitution: 0.5, density: 0.5, linear_damping: 0.1, angular_damping: 0.9 }
+        Self { friction: 0.5, restitution: 0.5, density: 0.5, linear_damping: 0.1, angular_damping: 0.9, layer: Layer::Units, ignore: LayerMask::NONE }
     }
 }
 
 impl PhysicsProperities {
     
     pub fn new(friction: f32, restitution: f32, density: f32, linear_damping: f32, angular_damping: f32) -> Self {
-        Self { friction, restitution, density, linear_damping, angular_damping }
+        Self { friction, restitution, density, linear_damping, angular_damping, layer: Layer::Units, ignore: LayerMask::NONE }
     }
 
     pub fn bounce() -> Self {
-        Self { friction: 0.0, restitution: 1.0, density: 1.0, linear_damping: 0.1, angular_damping: 0.1 }
+        Self { friction: 0.0, restitution: 1.0, density: 1.0, linear_damping: 0.1, angular_damping: 0.1, layer: Layer::Units, ignore: LayerMask::NONE }
     }
 
     pub fn free() -> Self {
-        Self { friction: 0.0, restitution: 1.0, density: 0.1, linear_damping: 0.01, angular_damping: 0.01 }
+        Self { friction: 0.0, restitution: 1.0, density: 0.1, linear_damping: 0.01, angular_damping: 0.01, layer: Layer::Units, ignore: LayerMask::NONE }
+    }
+
+    pub fn with_layer(mut self, layer: Layer) -> Self {
+        self.layer = layer;
+        return self;
+    }
+
+    pub fn ignoring(mut self, layers: &[Layer]) -> Self {
+        self.ignore = LayerMask::of(layers);
+        return self;
     }
 }
 
 
+/// What a ray ran into. Fixed bodies (boundary and scene walls, the ground) belong to no entity and are all `Wall`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hit {
+    Entity(u64),
+    Wall,
+}
+
+/// Collects rapier events during a step and maps them to entity keys through the bodies' `user_data`.
+#[derive(Default)]
+struct EventCollector {
+    events: Mutex<Vec<SimEvent>>,
+}
+
+impl EventCollector {
+
+    fn body_key(bodies: &RigidBodySet, colliders: &ColliderSet, handle: ColliderHandle) -> Option<u64> {
+        let parent = colliders.get(handle)?.parent()?;
+        let body = bodies.get(parent)?;
+        return Some(body.user_data as u64);
+    }
+
+    fn push(&self, event: SimEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn take(&self) -> Vec<SimEvent> {
+        return std::mem::take(&mut *self.events.lock().unwrap());
+    }
+}
+
+impl EventHandler for EventCollector {
+
+    fn handle_collision_event(&self, bodies: &RigidBodySet, colliders: &ColliderSet, event: CollisionEvent, _contact_pair: Option<&ContactPair>) {
+        let a = Self::body_key(bodies, colliders, event.collider1());
+        let b = Self::body_key(bodies, colliders, event.collider2());
+        let (Some(a), Some(b)) = (a, b) else {
+            return;
+        };
+        match event {
+            CollisionEvent::Started(..) => self.push(SimEvent::CollisionStarted { a, b }),
+            CollisionEvent::Stopped(..) => self.push(SimEvent::CollisionStopped { a, b }),
+        }
+    }
+
+    fn handle_contact_force_event(&self, dt: Real, bodies: &RigidBodySet, colliders: &ColliderSet, contact_pair: &ContactPair, total_force_magnitude: Real) {
+        let a = Self::body_key(bodies, colliders, contact_pair.collider1);
+        let b = Self::body_key(bodies, colliders, contact_pair.collider2);
+        let (Some(a), Some(b)) = (a, b) else {
+            return;
+        };
+        let event = ContactForceEvent::from_contact_pair(dt, contact_pair, total_force_magnitude);
+        let direction = Vec2::new(event.max_force_direction.x, event.max_force_direction.y);
+        self.push(SimEvent::ContactForce { a, b, force: total_force_magnitude, direction });
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Physics {
     pub attract_num: u32,
     pub rigid_bodies: RigidBodySet,
     pub colliders: ColliderSet,
     gravity: Vector2<f32>,
     integration_parameters: IntegrationParameters,
+    #[serde(skip)]
     physics_pipeline: PhysicsPipeline,
     island_manager: IslandManager,
     broad_phase: BroadPhase,
@@ -54,8 +222,12 @@ pub struct Physics {
     multibody_joint_set: MultibodyJointSet,
     ccd_solver: CCDSolver,
     query_pipeline: QueryPipeline,
+    #[serde(default)]
+    interactions: InteractionMatrix,
+    #[serde(skip)]
     physics_hooks: (),
-    event_handler: (),
+    #[serde(skip)]
+    event_handler: EventCollector,
 }
 
 impl Physics {
@@ -66,7 +238,7 @@ impl Physics {
             rigid_bodies: RigidBodySet::new(),
             colliders: ColliderSet::new(),
             gravity: Vector2::new(0.0, 0.0),
-            integration_parameters: IntegrationParameters::default(),
+            integration_parameters: IntegrationParameters { dt: FIX_DT, ..Default::default() },
             physics_pipeline: PhysicsPipeline::new(),
             island_manager: IslandManager::new(),
             broad_phase: BroadPhase::new(),
@@ -75,8 +247,9 @@ impl Physics {
             multibody_joint_set: MultibodyJointSet::new(),
             ccd_solver: CCDSolver::new(),
             query_pipeline: QueryPipeline::new(),
+            interactions: InteractionMatrix::default(),
             physics_hooks: (),
-            event_handler: (),
+            event_handler: EventCollector::default(),
         }
     }
 
@@ -98,8 +271,33 @@ impl Physics {
         );
     }
 
-    pub fn remove_physics_object(&mut self, body_handle: RigidBodyHandle) {
+    /// Collision and contact-force events gathered since the last call, one per touching collider pair.
+    pub fn take_events(&mut self) -> Vec<SimEvent> {
+        return self.event_handler.take();
+    }
+
+    /// Removes a body together with its colliders and every joint attached to it; returns the removed joints.
+    pub fn remove_physics_object(&mut self, body_handle: RigidBodyHandle) -> Vec<ImpulseJointHandle> {
+        let joints: Vec<ImpulseJointHandle> = self.impulse_joint_set.attached_joints(body_handle).map(|(_, _, handle, _)| handle).collect();
+        for joint in joints.iter() {
+            self.impulse_joint_set.remove(*joint, true);
+        }
         _ = self.rigid_bodies.remove(body_handle, &mut self.island_manager, &mut self.colliders, &mut self.impulse_joint_set, &mut self.multibody_joint_set, true);
+        return joints;
+    }
+
+    /// Casts a ray through the query pipeline, skipping the colliders of `exclude`. Returns the distance to the first hit, up to
+    /// `range`, and what it hit.
+    pub fn cast_ray(&self, origin: Vec2, dir: Vec2, range: f32, exclude: Option<RigidBodyHandle>) -> Option<(f32, Hit)> {
+        let ray = Ray::new(point![origin.x, origin.y], vector![dir.x, dir.y]);
+        let mut filter = QueryFilter::new().exclude_sensors();
+        if let Some(handle) = exclude {
+            filter = filter.exclude_rigid_body(handle);
+        }
+        let (collider, toi) = self.query_pipeline.cast_ray(&self.rigid_bodies, &self.colliders, &ray, range, true, filter)?;
+        let rb = self.get_body_handle_from_collider(collider).and_then(|handle| self.rigid_bodies.get(handle))?;
+        let hit = if rb.is_fixed() { Hit::Wall } else { Hit::Entity(rb.user_data as u64) };
+        return Some((toi, hit));
     }
 
     pub fn get_physics_obj_num(&self) -> usize {
@@ -108,11 +306,8 @@ impl Physics {
     }
 
     fn get_body_handle_from_collider(&self, collider_handle: ColliderHandle) -> Option<RigidBodyHandle> {
-        let collider: &Collider;
-        match self.colliders.get(collider_handle) {
-            Some(col) => {
-                collider = col;
-            }
+        let collider = match self.colliders.get(collider_handle) {
+            Some(col) => col,
             None => {
                 return None;
             }
@@ -134,9 +329,10 @@ impl Physics {
         return collider.shape().as_ball().unwrap().radius;
     }
 
+    /// Same convention as `util::iso_to_vec2_rot`: no offset between rapier's angle and the heading used for drawing and thrust.
     fn iso_to_vec2_rot(&self, isometry: &Isometry<Real>) -> (Vec2, f32) {
         let pos = Vec2::new(isometry.translation.x, isometry.translation.y);
-        let rot = isometry.rotation.angle() + PI;
+        let rot = isometry.rotation.angle();
         return (pos, rot);
     }
 
@@ -149,49 +345,83 @@ impl Physics {
 
     pub fn add_static_rigidbody(&mut self, key: u64, position: &Vec2, rotation: f32) -> RigidBodyHandle {
         let pos = Isometry2::new(Vector2::new(position.x, position.y), rotation);
-        let static_body = RigidBodyBuilder::fixed().position(pos).build();
+        let static_body = RigidBodyBuilder::fixed().position(pos).user_data(key as u128).build();
         return self.rigid_bodies.insert(static_body);
     }
 
     pub fn add_collider(&mut self, body_handle: RigidBodyHandle, rel_position: &Vec2, rotation: f32, shape: SharedShape, physics_props: PhysicsProperities) -> ColliderHandle {
         let iso = make_isometry(rel_position.x, rel_position.y, rotation);
-        let collider = match shape.shape_type() {
+        let builder = match shape.shape_type() {
             ShapeType::Ball => {
                 let radius = shape.0.as_ball().unwrap().radius;
-                ColliderBuilder::ball(radius).position(iso).density(physics_props.density).friction(physics_props.friction).restitution(physics_props.restitution)
-                    .active_collision_types(ActiveCollisionTypes::all()).active_events(ActiveEvents::COLLISION_EVENTS).build()
+                ColliderBuilder::ball(radius).position(iso).active_collision_types(ActiveCollisionTypes::all())
             },
             ShapeType::Triangle => {
                 let verts = shape.0.as_triangle().unwrap().vertices().to_vec();
-                ColliderBuilder::triangle(verts[0], verts[1], verts[2]).position(iso).density(physics_props.density).friction(physics_props.friction).restitution(physics_props.restitution)
-                .active_collision_types(ActiveCollisionTypes::default()).active_events(ActiveEvents::COLLISION_EVENTS).build()
+                ColliderBuilder::triangle(verts[0], verts[1], verts[2]).position(iso).active_collision_types(ActiveCollisionTypes::default())
             },
             ShapeType::Cuboid => {
                 let hx = shape.0.as_cuboid().unwrap().half_extents.x; let hy = shape.0.as_cuboid().unwrap().half_extents.y;
-                ColliderBuilder::cuboid(hx, hy).position(iso).density(physics_props.density).friction(physics_props.friction).restitution(physics_props.restitution)
-                .active_collision_types(ActiveCollisionTypes::default()).active_events(ActiveEvents::COLLISION_EVENTS).build()
+                ColliderBuilder::cuboid(hx, hy).position(iso).active_collision_types(ActiveCollisionTypes::default())
             },
             ShapeType::ConvexPolygon => {
                 let verts = shape.0.as_convex_polygon().unwrap().points();
-                ColliderBuilder::convex_polyline(verts.to_vec()).unwrap().density(physics_props.density).friction(physics_props.friction).restitution(physics_props.restitution)
-                .active_collision_types(ActiveCollisionTypes::all()).active_events(ActiveEvents::COLLISION_EVENTS).build()
+                ColliderBuilder::convex_polyline(verts.to_vec()).unwrap().position(iso).active_collision_types(ActiveCollisionTypes::all())
             },
             _ => {
-                ColliderBuilder::ball(5.0).position(iso).build()
+                let collider = ColliderBuilder::ball(5.0).position(iso).build();
+                return self.colliders.insert_with_parent(collider, body_handle, &mut self.rigid_bodies);
             },
         };
+        // Sensor-layer colliders only detect overlaps: they push nothing and rays pass through them,
+        // so only solid colliders report contact forces.
+        let sensor = physics_props.layer == Layer::Sensors;
+        let events = if sensor { ActiveEvents::COLLISION_EVENTS } else { ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS };
+        let collider = builder.density(physics_props.density).friction(physics_props.friction).restitution(physics_props.restitution)
+            .sensor(sensor)
+            .active_events(events)
+            .contact_force_event_threshold(CONTACT_FORCE_THRESHOLD)
+            .collision_groups(self.interactions.collision_groups(physics_props.layer, physics_props.ignore))
+            .solver_groups(self.interactions.solver_groups(physics_props.layer, physics_props.ignore))
+            .user_data(encode_layers(physics_props.layer, physics_props.ignore))
+            .build();
         return self.colliders.insert_with_parent(collider, body_handle, &mut self.rigid_bodies);
     }
 
-    pub fn add_dynamic(&mut self, key: u64, position: &Vec2, rotation: f32, shape: SharedShape, physics_props: PhysicsProperities) -> RigidBodyHandle {
+    pub fn get_interactions(&self) -> &InteractionMatrix {
+        return &self.interactions;
+    }
+
+    /// Replaces the interaction matrix and re-filters every existing collider against it.
+    pub fn set_interactions(&mut self, interactions: InteractionMatrix) {
+        self.interactions = interactions;
+        for (_, collider) in self.colliders.iter_mut() {
+            let (layer, ignore) = decode_layers(collider.user_data);
+            collider.set_collision_groups(self.interactions.collision_groups(layer, ignore));
+            collider.set_solver_groups(self.interactions.solver_groups(layer, ignore));
+        }
+    }
+
+    pub fn set_interaction(&mut self, a: Layer, b: Layer, interact: bool) {
+        let mut interactions = self.interactions.clone();
+        interactions.set(a, b, interact);
+        self.set_interactions(interactions);
+    }
+
+    pub fn add_dynamic(&mut self, key: u64, position: &Vec2, rotation: f32, shape: SharedShape, physics_props: PhysicsProperities, rng: &mut SimRng) -> RigidBodyHandle {
         let rbh = self.add_dynamic_rigidbody(key, position, rotation, physics_props.linear_damping, physics_props.angular_damping);
         let _colh = self.add_collider(rbh, &Vec2::ZERO, 0.0, shape, physics_props);
-        let rb = self.rigid_bodies.get_mut(rbh).unwrap();
-        let impulse = random_unit_vec2()*(SPEED as f32);
-        rb.apply_impulse(vector![impulse.x, impulse.y], true);
+        self.kick(rbh, rng);
         return rbh;
     }
 
+    /// Gives a newly spawned body its random initial push.
+    pub fn kick(&mut self, body_handle: RigidBodyHandle, rng: &mut SimRng) {
+        let rb = self.rigid_bodies.get_mut(body_handle).unwrap();
+        let impulse = random_unit_vec2(rng)*(SPEED as f32);
+        rb.apply_impulse(vector![impulse.x, impulse.y], true);
+    }
+
     pub fn add_static(&mut self, key: u64, position: &Vec2, rotation: f32, shape: SharedShape, physics_props: PhysicsProperities) -> RigidBodyHandle {
         let rbh = self.add_static_rigidbody(key, position, rotation);
         let _colh = self.add_collider(rbh, &Vec2::ZERO, 0.0, shape, physics_props);
@@ -201,11 +431,28 @@ impl Physics {
         return rbh;
     }
 
+    pub fn set_gravity(&mut self, gravity: Vec2) {
+        self.gravity = Vector2::new(gravity.x, gravity.y);
+    }
+
+    pub fn get_gravity(&self) -> Vec2 {
+        return Vec2::new(self.gravity.x, self.gravity.y);
+    }
+
+    /// Teleports a body without touching its velocities.
+    pub fn set_body_position(&mut self, handle: RigidBodyHandle, position: Vec2) {
+        if let Some(rb) = self.rigid_bodies.get_mut(handle) {
+            rb.set_translation(vector![position.x, position.y], true);
+        }
+    }
+
     pub fn add_ground(&mut self) -> RigidBodyHandle {
         let pos = Isometry2::new(Vector2::new(WORLD_W/2.0, 30.0), 0.0);
         let ground_body = RigidBodyBuilder::fixed().position(pos).build();
         let ground = self.rigid_bodies.insert(ground_body);
-        let collider = ColliderBuilder::cuboid(WORLD_W/2.0, 10.0).active_collision_types(ActiveCollisionTypes::DYNAMIC_FIXED).build();
+        let groups = self.interactions.collision_groups(Layer::Terrain, LayerMask::NONE);
+        let collider = ColliderBuilder::cuboid(WORLD_W/2.0, 10.0).active_collision_types(ActiveCollisionTypes::DYNAMIC_FIXED)
+            .collision_groups(groups).solver_groups(groups).user_data(encode_layers(Layer::Terrain, LayerMask::NONE)).build();
         self.colliders.insert_with_parent(collider, ground, &mut self.rigid_bodies);
         return ground
     }
@@ -221,6 +468,8 @@ impl Physics {
                 mass: rb.mass(),
                 kin_eng: Some(rb.kinetic_energy()),
                 force: Some(force),
+                velocity: Vec2::new(rb.linvel().x, rb.linvel().y),
+                spin: rb.angvel(),
             };
             return data;
         } else {
@@ -230,16 +479,80 @@ impl Physics {
                 mass: 0.0,
                 kin_eng: Some(0.0),
                 force: None,
+                velocity: Vec2::ZERO,
+                spin: 0.0,
             };
         }
     }
 
 }
 
+/// Colliders remember their layer and ignore mask in `user_data` so groups can be rebuilt when the matrix changes.
+fn encode_layers(layer: Layer, ignore: LayerMask) -> u128 {
+    return layer.index() as u128 | (ignore.0 as u128) << 8;
+}
+
+fn decode_layers(data: u128) -> (Layer, LayerMask) {
+    let layer = Layer::from_index((data & 0xff) as usize).unwrap_or(Layer::Units);
+    return (layer, LayerMask((data >> 8) as u32));
+}
+
 pub struct PhysicsData {
     pub position: Vec2,
     pub rotation: f32,
     pub mass: f32,
     pub kin_eng: Option<f32>,
     pub force: Option<Vec2>,
+    /// Linear velocity in world space and angular velocity in radians per second.
+    pub velocity: Vec2,
+    pub spin: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_pass_through_sensor_colliders() {
+        let mut physics = Physics::new();
+        let sensor = physics.add_static(7, &Vec2::new(50.0, 0.0), 0.0, SharedShape::ball(10.0), PhysicsProperities::default().with_layer(Layer::Sensors));
+        physics.add_static(9, &Vec2::new(100.0, 0.0), 0.0, SharedShape::ball(10.0), PhysicsProperities::default().with_layer(Layer::Units));
+        physics.step_physics();
+        let collider = physics.rigid_bodies[sensor].colliders()[0];
+        assert!(physics.colliders[collider].is_sensor());
+        let (distance, hit) = physics.cast_ray(Vec2::ZERO, Vec2::X, 200.0, None).unwrap();
+        assert_eq!(hit, Hit::Wall);
+        assert!((distance - 90.0).abs() < 1e-3, "hit at {}", distance);
+    }
+
+    #[test]
+    fn rays_tell_walls_from_entities_keyed_alike() {
+        let mut physics = Physics::new();
+        let unit = physics.add_dynamic_rigidbody(0, &Vec2::new(50.0, 0.0), 0.0, 0.0, 0.0);
+        physics.add_collider(unit, &Vec2::ZERO, 0.0, SharedShape::ball(10.0), PhysicsProperities::default().with_layer(Layer::Units));
+        physics.add_static(0, &Vec2::new(150.0, 0.0), 0.0, SharedShape::cuboid(10.0, 10.0), PhysicsProperities::default().with_layer(Layer::Terrain));
+        physics.step_physics();
+        assert_eq!(physics.cast_ray(Vec2::ZERO, Vec2::X, 200.0, None).map(|(_, hit)| hit), Some(Hit::Entity(0)));
+        assert_eq!(physics.cast_ray(Vec2::new(100.0, 0.0), Vec2::X, 200.0, None).map(|(_, hit)| hit), Some(Hit::Wall));
+    }
+
+    fn resting_contact_forces(gravity: f32) -> usize {
+        let mut physics = Physics::new();
+        physics.set_gravity(Vec2::new(0.0, gravity));
+        physics.add_static(1, &Vec2::new(0.0, 20.0), 0.0, SharedShape::cuboid(50.0, 10.0), PhysicsProperities::default().with_layer(Layer::Units));
+        let ball = physics.add_dynamic_rigidbody(2, &Vec2::new(0.0, 0.0), 0.0, 0.0, 0.0);
+        physics.add_collider(ball, &Vec2::ZERO, 0.0, SharedShape::ball(10.0), PhysicsProperities::default().with_layer(Layer::Units));
+        let mut forces = 0;
+        for _ in 0..30 {
+            physics.step_physics();
+            forces += physics.take_events().iter().filter(|event| matches!(event, SimEvent::ContactForce { .. })).count();
+        }
+        return forces;
+    }
+
+    #[test]
+    fn weak_contact_forces_raise_no_events() {
+        assert_eq!(resting_contact_forces(0.0001), 0);
+        assert!(resting_contact_forces(100.0) > 0);
+    }
 }
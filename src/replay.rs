@@ -0,0 +1,376 @@
+#![allow(unused)]
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use crate::sim::WorldBoundary;
+use crate::mixer::Wrench;
+use crate::autopilot::AutopilotMode;
+use crate::mission::Waypoint;
+use crate::controller::ControllerKind;
+
+/// Header line every replay file starts with.
+const REPLAY_HEADER: &str = "# drone replay v1";
+
+/// A single control input given to a jet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Go(f32),
+    TurnLeft,
+    TurnRight,
+    /// Stick position held until the next command: forward, lateral and turn, each in -1..1.
+    Stick(Wrench),
+    /// Hands the jet to its autopilot.
+    Engage(AutopilotMode),
+    Disengage,
+    /// Appends a waypoint to the jet's mission.
+    Waypoint(Waypoint),
+    /// Drops the mission and disengages the autopilot.
+    Abort,
+    /// Replaces the jet's controller with a fresh one of the given kind.
+    Control(ControllerKind),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Go(thrust) => write!(f, "go {}", thrust),
+            Command::TurnLeft => write!(f, "left"),
+            Command::TurnRight => write!(f, "right"),
+            Command::Stick(stick) => write!(f, "stick {} {} {}", stick.forward, stick.lateral, stick.torque),
+            Command::Engage(mode) => write!(f, "engage {}", mode),
+            Command::Disengage => write!(f, "disengage"),
+            Command::Waypoint(waypoint) => write!(f, "waypoint {}", waypoint),
+            Command::Abort => write!(f, "abort"),
+            Command::Control(kind) => write!(f, "controller {}", kind.name()),
+        }
+    }
+}
+
+impl Command {
+
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        match words {
+            ["go", thrust] => {
+                let thrust = thrust.parse().map_err(|_| format!("bad thrust '{}'", thrust))?;
+                return Ok(Command::Go(thrust));
+            },
+            ["left"] => Ok(Command::TurnLeft),
+            ["right"] => Ok(Command::TurnRight),
+            ["stick", axes @ ..] if axes.len() == 3 => {
+                let mut values = [0.0; 3];
+                for (value, word) in values.iter_mut().zip(axes) {
+                    *value = word.parse().map_err(|_| format!("bad stick axis '{}'", word))?;
+                }
+                return Ok(Command::Stick(Wrench::new(values[0], values[1], values[2])));
+            },
+            ["engage", mode @ ..] => Ok(Command::Engage(AutopilotMode::parse(mode)?)),
+            ["disengage"] => Ok(Command::Disengage),
+            ["waypoint", values @ ..] => Ok(Command::Waypoint(Waypoint::parse(values)?)),
+            ["abort"] => Ok(Command::Abort),
+            ["controller", name] => ControllerKind::parse(name).map(Command::Control).ok_or(format!("unknown controller '{}'", name)),
+            _ => Err(format!("unknown command '{}'", words.join(" "))),
+        }
+    }
+}
+
+/// Command addressed to the jet with key `jet`, applied just before physics step number `step` runs.
+#[derive(Clone, Copy, Debug)]
+pub struct ControlEvent {
+    pub step: u64,
+    pub jet: u64,
+    pub command: Command,
+}
+
+impl fmt::Display for ControlEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.step, self.jet, self.command)
+    }
+}
+
+impl ControlEvent {
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 3 {
+            return Err(format!("expected '<step> <jet> <command>', got '{}'", line));
+        }
+        let step = words[0].parse().map_err(|_| format!("bad step '{}'", words[0]))?;
+        let jet = words[1].parse().map_err(|_| format!("bad jet key '{}'", words[1]))?;
+        let command = Command::parse(&words[2..])?;
+        return Ok(Self { step, jet, command });
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Parse { line: usize, msg: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "replay i/o error: {}", err),
+            ReplayError::Parse { line, msg } => write!(f, "replay line {}: {}", line, msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(err: std::io::Error) -> Self {
+        return ReplayError::Io(err);
+    }
+}
+
+/// Seed and world settings plus every control command of a run, ordered by step. Stored as plain text, one command per line.
+#[derive(Clone, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    /// Scene file the run started from; `None` is the built-in default scene.
+    pub scene: Option<String>,
+    pub boundary: WorldBoundary,
+    pub events: Vec<ControlEvent>,
+}
+
+impl Replay {
+
+    pub fn new(seed: u64, scene: Option<String>, boundary: WorldBoundary) -> Self {
+        Self { seed, scene, boundary, events: vec![] }
+    }
+
+    pub fn header(&self) -> String {
+        let mut header = format!("{}\nseed {}\n", REPLAY_HEADER, self.seed);
+        if let Some(scene) = &self.scene {
+            header.push_str(&format!("scene {}\n", scene));
+        }
+        header.push_str(&format!("boundary {}\n", self.boundary.name()));
+        return header;
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = self.header();
+        for event in self.events.iter() {
+            text.push_str(&event.to_string());
+            text.push('\n');
+        }
+        return text;
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ReplayError> {
+        let mut seed: Option<u64> = None;
+        let mut scene: Option<String> = None;
+        let mut boundary = WorldBoundary::default();
+        let mut events: Vec<ControlEvent> = vec![];
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let err = |msg: String| ReplayError::Parse { line: num + 1, msg };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("seed ") {
+                seed = Some(value.trim().parse().map_err(|_| err(format!("bad seed '{}'", value)))?);
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("scene ") {
+                scene = Some(value.trim().to_string());
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("boundary ") {
+                boundary = WorldBoundary::parse(value.trim()).ok_or_else(|| err(format!("bad boundary '{}'", value)))?;
+                continue;
+            }
+            let event = ControlEvent::parse(line).map_err(err)?;
+            if let Some(last) = events.last() {
+                if event.step < last.step {
+                    return Err(err(format!("step {} goes back in time", event.step)));
+                }
+            }
+            events.push(event);
+        }
+        let seed = seed.ok_or(ReplayError::Parse { line: 0, msg: "missing 'seed' line".to_string() })?;
+        return Ok(Self { seed, scene, boundary, events });
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let text = fs::read_to_string(path)?;
+        return Self::parse(&text);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        fs::write(path, self.to_text())?;
+        return Ok(());
+    }
+
+    /// Copy holding only the commands issued before `step`, the starting point for branching a replay.
+    pub fn truncated(&self, step: u64) -> Self {
+        let events = self.events.iter().filter(|event| event.step < step).copied().collect();
+        return Self { seed: self.seed, scene: self.scene.clone(), boundary: self.boundary, events };
+    }
+
+    pub fn last_step(&self) -> u64 {
+        return self.events.last().map(|event| event.step).unwrap_or(0);
+    }
+}
+
+/// Logs commands as they are issued; when given a file every line is written through immediately.
+pub struct Recorder {
+    pub replay: Replay,
+    file: Option<File>,
+    /// Why writing through to the file stopped; the in-memory replay keeps every command regardless.
+    error: Option<ReplayError>,
+}
+
+impl Recorder {
+
+    /// Starts an in-memory recording; `header` carries the seed and world settings, its events are dropped.
+    pub fn new(header: Replay) -> Self {
+        Self { replay: header.truncated(0), file: None, error: None }
+    }
+
+    pub fn to_file(header: Replay, path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let replay = header.truncated(0);
+        let mut file = File::create(path)?;
+        file.write_all(replay.header().as_bytes())?;
+        return Ok(Self { replay, file: Some(file), error: None });
+    }
+
+    pub fn record(&mut self, event: ControlEvent) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(err) = writeln!(file, "{}", event) {
+                self.error = Some(err.into());
+                self.file = None;
+            }
+        }
+        self.replay.events.push(event);
+    }
+
+    /// Hands over the error that stopped the file recording, once.
+    pub fn take_error(&mut self) -> Option<ReplayError> {
+        return self.error.take();
+    }
+}
+
+/// Feeds a recorded run back step by step, optionally stopping early so live control can branch off.
+pub struct Playback {
+    replay: Replay,
+    cursor: usize,
+    stop_at: Option<u64>,
+}
+
+impl Playback {
+
+    pub fn new(replay: Replay, stop_at: Option<u64>) -> Self {
+        Self { replay, cursor: 0, stop_at }
+    }
+
+    /// Commands scheduled for `step`; the cursor moves past them.
+    pub fn due(&mut self, step: u64) -> Vec<ControlEvent> {
+        let mut events = vec![];
+        if self.is_finished(step) {
+            return events;
+        }
+        while let Some(event) = self.replay.events.get(self.cursor) {
+            if event.step > step {
+                break;
+            }
+            events.push(*event);
+            self.cursor += 1;
+        }
+        return events;
+    }
+
+    pub fn is_finished(&self, step: u64) -> bool {
+        match self.stop_at {
+            Some(stop) => step >= stop,
+            None => self.cursor >= self.replay.events.len(),
+        }
+    }
+
+    pub fn get_replay(&self) -> &Replay {
+        return &self.replay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::prelude::Vec2;
+    use crate::scene::SceneDef;
+    use crate::sim::Simulation;
+    use crate::events::SimEvent;
+
+    fn commands() -> Vec<Command> {
+        let waypoint = Waypoint { radius: Some(12.5), heading: Some(-0.75), loiter: 2.0, ..Waypoint::at(Vec2::new(300.0, 250.0)) };
+        return vec![
+            Command::Go(0.5),
+            Command::TurnLeft,
+            Command::TurnRight,
+            Command::Stick(Wrench::new(0.25, -1.0, 0.125)),
+            Command::Engage(AutopilotMode::Heading(1.5)),
+            Command::Engage(AutopilotMode::Speed { speed: 40.0, heading: -0.3 }),
+            Command::Engage(AutopilotMode::Position(Vec2::new(400.0, 120.5))),
+            Command::Engage(AutopilotMode::Pose { target: Vec2::new(10.0, 20.0), heading: 3.0 }),
+            Command::Disengage,
+            Command::Waypoint(waypoint),
+            Command::Waypoint(Waypoint::at(Vec2::new(-5.0, 7.0))),
+            Command::Abort,
+            Command::Control(ControllerKind::External),
+        ];
+    }
+
+    #[test]
+    fn text_format_round_trips() {
+        let mut replay = Replay::new(42, Some("scenes/default.ron".to_string()), WorldBoundary::Toroidal);
+        for (i, command) in commands().into_iter().enumerate() {
+            replay.events.push(ControlEvent { step: 3 * i as u64, jet: 77, command });
+        }
+        let text = replay.to_text();
+        let parsed = Replay::parse(&text).unwrap();
+        assert_eq!(parsed.seed, 42);
+        assert_eq!(parsed.scene.as_deref(), Some("scenes/default.ron"));
+        assert_eq!(parsed.boundary, WorldBoundary::Toroidal);
+        let steps: Vec<(u64, u64, Command)> = parsed.events.iter().map(|event| (event.step, event.jet, event.command)).collect();
+        let expected: Vec<(u64, u64, Command)> = replay.events.iter().map(|event| (event.step, event.jet, event.command)).collect();
+        assert_eq!(steps, expected);
+        assert_eq!(parsed.to_text(), text);
+    }
+
+    #[test]
+    fn replayed_run_matches_the_recorded_one() {
+        let scene = SceneDef::default_scene();
+        let mut live = Simulation::from_scene(9, &scene).unwrap();
+        live.recorder = Some(Recorder::new(Replay::new(9, None, live.get_boundary())));
+        let jet = live.get_jets().next().unwrap().body.key;
+        let script = [
+            (5, Command::Stick(Wrench::new(1.0, 0.0, 0.3))),
+            (20, Command::Stick(Wrench::new(0.0, -0.5, 0.0))),
+            (35, Command::Engage(AutopilotMode::Pose { target: Vec2::new(500.0, 300.0), heading: 1.0 })),
+            (60, Command::Disengage),
+        ];
+        for step in 0..80 {
+            for (at, command) in script.iter() {
+                if *at == step {
+                    assert!(live.command(jet, *command));
+                }
+            }
+            live.step();
+        }
+        let replay = Replay::parse(&live.recorder.take().unwrap().replay.to_text()).unwrap();
+        assert_eq!(replay.events.len(), script.len());
+        assert!(!live.command(jet + 1, Command::Disengage));
+        let mut replayed = Simulation::from_scene(replay.seed, &scene).unwrap();
+        replayed.playback = Some(Playback::new(replay, None));
+        let mut finished = vec![];
+        for _ in 0..80 {
+            replayed.step();
+            finished.extend(replayed.step_events().iter().copied().filter(|event| matches!(event, SimEvent::ReplayFinished { .. })));
+        }
+        assert_eq!(finished, vec![SimEvent::ReplayFinished { step: 60 }]);
+        let state = |sim: &Simulation| sim.get_jets().map(|jet| (jet.body.key, jet.body.pos, jet.body.rot)).collect::<Vec<_>>();
+        assert_eq!(state(&live), state(&replayed));
+        let units = |sim: &Simulation| sim.entities.get_iter().map(|entity| entity.body().pos).collect::<Vec<_>>();
+        assert_eq!(units(&live), units(&replayed));
+    }
+}
@@ -0,0 +1,75 @@
+#![allow(unused)]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+/// Small seedable generator (xoshiro256**) so every random decision in a `Simulation` is reproducible from one seed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimRng {
+    state: [u64; 4],
+}
+
+impl SimRng {
+
+    pub fn new(seed: u64) -> Self {
+        let mut sm = seed;
+        let mut state = [0u64; 4];
+        for s in state.iter_mut() {
+            *s = splitmix64(&mut sm);
+        }
+        Self { state }
+    }
+
+    /// Seed taken from the wall clock, for runs that don't care about reproducibility.
+    pub fn time_seed() -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        return now.as_nanos() as u64;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        return result;
+    }
+
+    /// Uniform float in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        return (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+    }
+
+    /// Uniform float in `[low, high)`.
+    pub fn gen_range(&mut self, low: f32, high: f32) -> f32 {
+        return low + (high - low) * self.next_f32();
+    }
+
+    /// Standard normal sample (Box-Muller).
+    pub fn gaussian(&mut self) -> f32 {
+        let u1 = 1.0 - self.next_f32();
+        let u2 = self.next_f32();
+        return (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+    }
+
+    /// Uniform integer in `[low, high)`.
+    pub fn gen_range_u32(&mut self, low: u32, high: u32) -> u32 {
+        return low + (self.next_u64() % (high - low) as u64) as u32;
+    }
+
+    /// Uniform index in `[0, len)`.
+    pub fn gen_index(&mut self, len: usize) -> usize {
+        return (self.next_u64() % len as u64) as usize;
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    return z ^ (z >> 31);
+}
@@ -0,0 +1,457 @@
+#![allow(unused)]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use macroquad::prelude::*;
+use rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::jet::*;
+use crate::physics::*;
+use crate::sim::*;
+use crate::unit::*;
+use crate::util::*;
+use crate::consts::*;
+use crate::mixer::Wrench;
+use crate::autopilot::*;
+use crate::mission::*;
+use crate::sensors::*;
+use crate::estimator::*;
+use crate::controller::*;
+use crate::rng::SimRng;
+
+/// Scene used when nothing else is requested.
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.ron");
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "scene i/o error: {}", err),
+            SceneError::Parse(msg) => write!(f, "malformed scene: {}", msg),
+            SceneError::Invalid(msg) => write!(f, "invalid scene: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(err: std::io::Error) -> Self {
+        return SceneError::Io(err);
+    }
+}
+
+/// Named physics material; unset fields fall back to `PhysicsProperities::default()`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialDef {
+    pub friction: f32,
+    pub restitution: f32,
+    pub density: f32,
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    pub layer: Layer,
+    pub ignore: Vec<Layer>,
+}
+
+impl Default for MaterialDef {
+    fn default() -> Self {
+        return Self::from_properties(&PhysicsProperities::default());
+    }
+}
+
+impl MaterialDef {
+
+    pub fn from_properties(props: &PhysicsProperities) -> Self {
+        let ignore = Layer::ALL.iter().copied().filter(|layer| props.ignore.contains(*layer)).collect();
+        return Self {
+            friction: props.friction,
+            restitution: props.restitution,
+            density: props.density,
+            linear_damping: props.linear_damping,
+            angular_damping: props.angular_damping,
+            layer: props.layer,
+            ignore,
+        };
+    }
+
+    pub fn to_properties(&self) -> PhysicsProperities {
+        return PhysicsProperities {
+            friction: self.friction,
+            restitution: self.restitution,
+            density: self.density,
+            linear_damping: self.linear_damping,
+            angular_damping: self.angular_damping,
+            layer: self.layer,
+            ignore: LayerMask::of(&self.ignore),
+        };
+    }
+}
+
+/// Static rectangle; `size` is the full width and height.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WallDef {
+    pub pos: Vec2,
+    pub size: Vec2,
+    /// Heading in radians; see `SceneDef` for the convention.
+    #[serde(default)]
+    pub rot: f32,
+    #[serde(default)]
+    pub material: Option<String>,
+}
+
+/// One part of a composite unit, placed relative to the unit's origin; color and material default to the unit's.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BodyPartDef {
+    pub shape: PartShape,
+    #[serde(default)]
+    pub offset: Vec2,
+    /// Relative to the unit's heading, in radians.
+    #[serde(default)]
+    pub rot: f32,
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub material: Option<String>,
+}
+
+/// One or more dynamic units. Anything left out is randomised from the simulation seed.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnitDef {
+    pub name: Option<String>,
+    pub count: Option<usize>,
+    pub pos: Option<Vec2>,
+    /// Heading in radians; see `SceneDef` for the convention.
+    pub rot: f32,
+    /// Ignored when `parts` are given.
+    pub size: Option<f32>,
+    pub color: Option<[f32; 4]>,
+    pub material: Option<String>,
+    /// Builds the unit from these parts instead of the usual three balls.
+    pub parts: Vec<BodyPartDef>,
+}
+
+/// Engine mounted at `pos` in the hull's frame, pushing along `dir`; a jet may list any number of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EngineDef {
+    pub pos: Vec2,
+    pub dir: Vec2,
+    pub max_thrust: f32,
+    /// Defaults to -1, a fully reversible engine; a positive value is an idle thrust it never drops below.
+    #[serde(default)]
+    pub min_throttle: Option<f32>,
+    #[serde(default)]
+    pub spool_up: Option<f32>,
+    #[serde(default)]
+    pub spool_down: Option<f32>,
+    /// Nozzle deflection limit either side of `dir`, in radians, and its slew rate in radians per second; omitted means a fixed nozzle.
+    #[serde(default)]
+    pub gimbal: Option<(f32, f32)>,
+}
+
+/// Distance sensor mounted at `pos` in the hull's frame; one beam is a rangefinder, more spread over `fov` make a lidar.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SensorDef {
+    #[serde(default)]
+    pub pos: Vec2,
+    /// Middle of the fan, in radians from the nose.
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub beams: Option<usize>,
+    #[serde(default)]
+    pub fov: f32,
+    pub range: f32,
+    /// Standard deviation of the distance noise, in px.
+    #[serde(default)]
+    pub noise: f32,
+}
+
+/// Controller a jet starts with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ControllerDef {
+    Keyboard,
+    Autopilot,
+    Scripted { keys: Vec<ScriptKey>, #[serde(default)] repeat: bool },
+    External,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JetDef {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub pos: Vec2,
+    /// Heading in radians, turning from world +x towards +y; 0 points the hull's local +x, its nose, along world +x.
+    #[serde(default)]
+    pub rot: f32,
+    pub hull: Vec<Vec2>,
+    /// Empty means `Jet::default_engines()`.
+    #[serde(default)]
+    pub engines: Vec<EngineDef>,
+    /// Throttle the jet settles to when the stick is centred.
+    #[serde(default)]
+    pub neutral: Wrench,
+    /// Omitted means a keyboard-flown jet with an autopilot.
+    #[serde(default)]
+    pub controller: Option<ControllerDef>,
+    /// Autopilot tuning; omitted means `AutopilotGains::default()`.
+    #[serde(default)]
+    pub autopilot: Option<AutopilotGains>,
+    /// Waypoints the jet starts flying as soon as the scene runs.
+    #[serde(default)]
+    pub mission: Option<Mission>,
+    #[serde(default)]
+    pub sensors: Vec<SensorDef>,
+    #[serde(default)]
+    pub imu: Option<ImuSpec>,
+    #[serde(default)]
+    pub gps: Option<GpsSpec>,
+    /// Runs a state estimator on the jet's thrust and sensors.
+    #[serde(default)]
+    pub estimator: Option<EkfSpec>,
+    #[serde(default)]
+    pub material: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum JointKind {
+    Fixed,
+    Revolute,
+    Prismatic { axis: Vec2, limits: Option<(f32, f32)> },
+}
+
+/// Joint between two named units or jets; anchors are in each body's local frame.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JointDef {
+    pub a: String,
+    pub b: String,
+    #[serde(default)]
+    pub anchor_a: Vec2,
+    #[serde(default)]
+    pub anchor_b: Vec2,
+    pub kind: JointKind,
+}
+
+/// Declarative description of a world, loaded from RON.
+///
+/// A `rot` is a heading in radians, turning from world +x towards +y (a part's from its unit's heading), and every local
+/// frame (hulls, engines, parts, anchors) has its +x axis along that heading. Earlier builds drew bodies and applied
+/// thrust half a turn away from their physics heading, so a scene written for them now loads facing the opposite way
+/// unless `PI` is added to its `rot` values.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneDef {
+    pub gravity: Vec2,
+    pub boundary: WorldBoundary,
+    pub materials: BTreeMap<String, MaterialDef>,
+    /// Overrides to the default interaction matrix: (layer, layer, collide?).
+    pub interactions: Vec<(Layer, Layer, bool)>,
+    pub walls: Vec<WallDef>,
+    pub units: Vec<UnitDef>,
+    pub jets: Vec<JetDef>,
+    pub joints: Vec<JointDef>,
+}
+
+impl SceneDef {
+
+    pub fn default_scene() -> Self {
+        return Self::parse(DEFAULT_SCENE).expect("built-in scene parses");
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SceneError> {
+        return ron::from_str(text).map_err(|err| SceneError::Parse(err.to_string()));
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let text = fs::read_to_string(path)?;
+        return Self::parse(&text);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        let config = ron::ser::PrettyConfig::new().struct_names(false);
+        let text = ron::ser::to_string_pretty(self, config).map_err(|err| SceneError::Parse(err.to_string()))?;
+        fs::write(path, text)?;
+        return Ok(());
+    }
+
+    /// Resolves a material name: scene materials first, then the built-in `default`, `bounce` and `free`.
+    pub fn material(&self, name: &Option<String>, fallback: PhysicsProperities) -> Result<PhysicsProperities, SceneError> {
+        let Some(name) = name else {
+            return Ok(fallback);
+        };
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.to_properties());
+        }
+        match name.as_str() {
+            "default" => Ok(PhysicsProperities::default()),
+            "bounce" => Ok(PhysicsProperities::bounce()),
+            "free" => Ok(PhysicsProperities::free()),
+            _ => Err(SceneError::Invalid(format!("unknown material '{}'", name))),
+        }
+    }
+
+    /// Spawns everything the scene describes into `sim` through the regular spawn functions.
+    pub fn build(&self, sim: &mut Simulation) -> Result<(), SceneError> {
+        sim.physics.set_gravity(self.gravity);
+        let mut interactions = InteractionMatrix::default();
+        for (a, b, interact) in self.interactions.iter() {
+            interactions.set(*a, *b, *interact);
+        }
+        sim.physics.set_interactions(interactions);
+        sim.set_boundary(self.boundary);
+        for wall in self.walls.iter() {
+            let props = self.material(&wall.material, PhysicsProperities::default().with_layer(Layer::Terrain))?;
+            sim.add_wall(wall.pos, wall.size / 2.0, wall.rot, props);
+        }
+        let mut names: BTreeMap<String, u64> = BTreeMap::new();
+        for def in self.jets.iter() {
+            if convex_hull_shape(&def.hull).is_none() {
+                return Err(SceneError::Invalid("jet hull needs at least three vertices not in a line".to_string()));
+            }
+            let props = self.material(&def.material, PhysicsProperities::default().with_layer(Layer::Jets))?;
+            let engines = match def.engines.is_empty() {
+                true => Jet::default_engines(),
+                false => def.engines.iter().map(|eng| {
+                    let engine = JetEng::new(eng.pos, eng.dir, eng.max_thrust);
+                    let engine = engine.with_spool(eng.min_throttle.unwrap_or(-1.0), eng.spool_up.unwrap_or(SPOOL_UP), eng.spool_down.unwrap_or(SPOOL_DOWN));
+                    return match eng.gimbal {
+                        Some((limit, slew_rate)) => engine.with_gimbal(limit, slew_rate),
+                        None => engine,
+                    };
+                }).collect(),
+            };
+            let mut jet = Jet::new_dyn_poly(def.hull.clone(), def.pos, def.rot, engines, props, &mut sim.physics, &mut sim.rng);
+            jet.neutral = def.neutral;
+            jet.autopilot_gains = def.autopilot.unwrap_or_default();
+            jet.controller = match def.controller.clone().unwrap_or(ControllerDef::Autopilot) {
+                ControllerDef::Keyboard => Pilot::of_kind(ControllerKind::Keyboard, jet.autopilot_gains),
+                ControllerDef::Autopilot => Pilot::of_kind(ControllerKind::Autopilot, jet.autopilot_gains),
+                ControllerDef::Scripted { keys, repeat } => Pilot::Scripted(Scripted::new(keys, repeat)),
+                ControllerDef::External => Pilot::of_kind(ControllerKind::External, jet.autopilot_gains),
+            };
+            jet.mission = def.mission.clone().map(MissionProgress::new);
+            // Each sensor gets its own noise stream, derived from the jet's key so the world's generator isn't disturbed.
+            let mut seeds = SimRng::new(jet.body.key);
+            jet.sensors = def.sensors.iter().map(|sensor| {
+                return RangeSensor::lidar(sensor.pos, sensor.angle, sensor.beams.unwrap_or(1), sensor.fov, sensor.range).with_noise(sensor.noise, seeds.next_u64());
+            }).collect();
+            jet.imu = def.imu.map(|spec| Imu::new(spec, seeds.next_u64()));
+            jet.gps = def.gps.map(|spec| Gps::new(spec, seeds.next_u64()));
+            jet.estimator = def.estimator.map(|spec| Ekf::new(spec, def.pos, def.rot));
+            let key = sim.spawn(jet);
+            if let Some(name) = &def.name {
+                names.insert(name.clone(), key);
+            }
+        }
+        for def in self.units.iter() {
+            let count = def.count.unwrap_or(1);
+            if def.name.is_some() && count != 1 {
+                return Err(SceneError::Invalid("only a single unit can be named".to_string()));
+            }
+            let props = self.material(&def.material, PhysicsProperities::default())?;
+            let scheme = self.parts_scheme(&def.parts)?;
+            for _ in 0..count {
+                let unit = self.build_unit(def, &scheme, props.clone(), sim);
+                let key = sim.spawn(unit);
+                if let Some(name) = &def.name {
+                    names.insert(name.clone(), key);
+                }
+            }
+        }
+        for def in self.joints.iter() {
+            let lookup = |name: &String| names.get(name).copied().ok_or_else(|| SceneError::Invalid(format!("joint refers to unknown entity '{}'", name)));
+            let a = lookup(&def.a)?;
+            let b = lookup(&def.b)?;
+            let anchor_a = vec2_to_point2(&def.anchor_a);
+            let anchor_b = vec2_to_point2(&def.anchor_b);
+            let joint: GenericJoint = match &def.kind {
+                JointKind::Fixed => FixedJointBuilder::new().local_anchor1(anchor_a).local_anchor2(anchor_b).build().into(),
+                JointKind::Revolute => RevoluteJointBuilder::new().local_anchor1(anchor_a).local_anchor2(anchor_b).build().into(),
+                JointKind::Prismatic { axis, limits } => {
+                    if axis.length_squared() <= f32::EPSILON {
+                        return Err(SceneError::Invalid(format!("prismatic joint between '{}' and '{}' needs a non-zero axis", def.a, def.b)));
+                    }
+                    let axis = UnitVector::new_normalize(vector![axis.x, axis.y]);
+                    let mut builder = PrismaticJointBuilder::new(axis).local_anchor1(anchor_a).local_anchor2(anchor_b);
+                    if let Some((min, max)) = limits {
+                        builder = builder.limits([*min, *max]);
+                    }
+                    builder.build().into()
+                },
+            };
+            if sim.add_joint(a, b, joint).is_none() {
+                return Err(SceneError::Invalid(format!("can't join '{}' and '{}'", def.a, def.b)));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Resolves part materials and checks every convex part has a hull; no parts makes an empty scheme.
+    fn parts_scheme(&self, parts: &[BodyPartDef]) -> Result<PartsScheme, SceneError> {
+        let mut scheme = PartsScheme::default();
+        for part in parts.iter() {
+            if part.shape.to_shape().is_none() {
+                return Err(SceneError::Invalid("convex unit part needs at least three vertices not in a line".to_string()));
+            }
+            let material = match &part.material {
+                Some(_) => Some(self.material(&part.material, PhysicsProperities::default())?),
+                None => None,
+            };
+            let color = part.color.map(|[r, g, b, a]| Color::new(r, g, b, a));
+            scheme.parts.push(PartDef { shape: part.shape.clone(), offset: part.offset, rot: part.rot, color, material });
+        }
+        return Ok(scheme);
+    }
+
+    fn build_unit(&self, def: &UnitDef, scheme: &PartsScheme, props: PhysicsProperities, sim: &mut Simulation) -> Unit {
+        let rng = &mut sim.rng;
+        let size = match scheme.parts.is_empty() {
+            true => def.size.unwrap_or_else(|| rng.gen_range_u32(SIZE_MIN, SIZE_MAX) as f32),
+            false => 0.0,
+        };
+        let color = match def.color {
+            Some([r, g, b, a]) => Color::new(r, g, b, a),
+            None => random_color(rng),
+        };
+        let pos = def.pos.unwrap_or_else(|| random_position(WORLD_W, WORLD_H, rng));
+        if !scheme.parts.is_empty() {
+            return Unit::from_scheme(scheme, pos, def.rot, color, props, &mut sim.physics, &mut sim.rng);
+        }
+        return Unit::build(pos, def.rot, size, color, props, &mut sim.physics, &mut sim.rng);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(text: &str) -> Result<(), SceneError> {
+        let scene = SceneDef::parse(text).expect("test scene parses");
+        return scene.build(&mut Simulation::with_seed(1));
+    }
+
+    #[test]
+    fn degenerate_jet_hull_is_invalid() {
+        let collinear = "(jets: [(pos: (100.0, 100.0), hull: [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)])])";
+        assert!(matches!(build(collinear), Err(SceneError::Invalid(_))));
+        let duplicate = "(jets: [(pos: (100.0, 100.0), hull: [(1.0, 1.0), (1.0, 1.0), (1.0, 1.0)])])";
+        assert!(matches!(build(duplicate), Err(SceneError::Invalid(_))));
+    }
+
+    #[test]
+    fn prismatic_joint_needs_an_axis() {
+        let scene = r#"(
+            units: [(name: Some("a"), pos: Some((100.0, 100.0))), (name: Some("b"), pos: Some((200.0, 100.0)))],
+            joints: [(a: "a", b: "b", kind: Prismatic(axis: (0.0, 0.0), limits: None))],
+        )"#;
+        assert!(matches!(build(scene), Err(SceneError::Invalid(_))));
+        assert!(build(&scene.replace("axis: (0.0, 0.0)", "axis: (0.0, 1.0)")).is_ok());
+    }
+}
@@ -0,0 +1,321 @@
+#![allow(unused)]
+
+use std::collections::VecDeque;
+use macroquad::prelude::*;
+use rapier2d::prelude::RigidBodyHandle;
+use serde::{Deserialize, Serialize};
+use crate::physics::{Hit, Physics};
+use crate::rng::SimRng;
+
+/// One beam's result: distance along the beam, `range` when nothing was hit, and what it hit.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RangeReading {
+    pub distance: f32,
+    pub hit: Option<Hit>,
+}
+
+/// Distance sensor casting one or more rays from a mounting point on the jet. A single beam makes a rangefinder,
+/// several spread evenly over `fov` make a lidar.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RangeSensor {
+    /// Mounting point in the jet's body frame.
+    pub pos: Vec2,
+    /// Direction of the middle of the fan, in radians from the jet's nose.
+    pub angle: f32,
+    pub beams: usize,
+    /// Angle between the first and last beam.
+    pub fov: f32,
+    pub range: f32,
+    /// Standard deviation of the noise added to each hit distance, in px.
+    pub noise: f32,
+    readings: Vec<RangeReading>,
+    rng: SimRng,
+}
+
+impl RangeSensor {
+
+    pub fn rangefinder(pos: Vec2, angle: f32, range: f32) -> Self {
+        return Self::lidar(pos, angle, 1, 0.0, range);
+    }
+
+    pub fn lidar(pos: Vec2, angle: f32, beams: usize, fov: f32, range: f32) -> Self {
+        let beams = beams.max(1);
+        Self { pos, angle, beams, fov, range, noise: 0.0, readings: vec![RangeReading { distance: range, hit: None }; beams], rng: SimRng::new(0) }
+    }
+
+    /// Adds Gaussian noise with standard deviation `noise` px, drawn from a generator seeded with `seed`.
+    pub fn with_noise(mut self, noise: f32, seed: u64) -> Self {
+        self.noise = noise;
+        self.rng = SimRng::new(seed);
+        return self;
+    }
+
+    /// Direction of beam `i` in the jet's body frame, as an angle from the nose.
+    pub fn beam_angle(&self, i: usize) -> f32 {
+        if self.beams < 2 {
+            return self.angle;
+        }
+        return self.angle - self.fov / 2.0 + self.fov * i as f32 / (self.beams - 1) as f32;
+    }
+
+    pub fn get_readings(&self) -> &[RangeReading] {
+        return &self.readings;
+    }
+
+    /// Casts every beam from the jet's current pose, ignoring the jet's own body.
+    pub fn sample(&mut self, physics: &Physics, handle: RigidBodyHandle) {
+        let state = physics.get_physics_data(handle);
+        let origin = state.position + Vec2::from_angle(state.rotation).rotate(self.pos);
+        for i in 0..self.beams {
+            let dir = Vec2::from_angle(state.rotation + self.beam_angle(i));
+            self.readings[i] = match physics.cast_ray(origin, dir, self.range, Some(handle)) {
+                Some((distance, hit)) => {
+                    let noisy = distance + self.noise * self.rng.gaussian();
+                    RangeReading { distance: noisy.clamp(0.0, self.range), hit: Some(hit) }
+                },
+                None => RangeReading { distance: self.range, hit: None },
+            };
+        }
+    }
+
+    /// Draws each beam from the mount at the given jet pose out to its last reading, marking hits.
+    pub fn draw(&self, pos: Vec2, rot: f32) {
+        let origin = pos + Vec2::from_angle(rot).rotate(self.pos);
+        for (i, reading) in self.readings.iter().enumerate() {
+            let end = origin + Vec2::from_angle(rot + self.beam_angle(i)) * reading.distance;
+            let color = if reading.hit.is_some() { Color::new(1.0, 0.3, 0.3, 0.5) } else { Color::new(0.0, 0.9, 0.3, 0.25) };
+            draw_line(origin.x, origin.y, end.x, end.y, 1.0, color);
+            if reading.hit.is_some() {
+                draw_circle(end.x, end.y, 2.0, RED);
+            }
+        }
+    }
+}
+
+/// Fixed-rate sample clock shared by the IMU and position sensor. Keeps the remainder so a rate that doesn't divide the
+/// physics step still averages out right.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct SampleClock {
+    time: f32,
+    since_sample: f32,
+}
+
+impl SampleClock {
+
+    /// Advances by `dt` and returns whether a sample is due at `rate` Hz.
+    fn tick(&mut self, dt: f32, rate: f32) -> bool {
+        self.time += dt;
+        self.since_sample += dt;
+        let period = 1.0 / rate.max(f32::EPSILON);
+        if self.since_sample + 1e-6 < period {
+            return false;
+        }
+        self.since_sample = (self.since_sample - period).min(period);
+        return true;
+    }
+}
+
+/// IMU noise model. Noise values are standard deviations per sample; bias walks are per square root of a second.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImuSpec {
+    /// Samples per second; anything at or above the physics rate samples every step.
+    pub rate: f32,
+    /// Gyro white noise, in rad/s.
+    pub gyro_noise: f32,
+    pub gyro_walk: f32,
+    /// Accelerometer white noise, in px/s².
+    pub accel_noise: f32,
+    pub accel_walk: f32,
+}
+
+impl Default for ImuSpec {
+    fn default() -> Self {
+        Self { rate: 30.0, gyro_noise: 0.01, gyro_walk: 0.002, accel_noise: 2.0, accel_walk: 0.5 }
+    }
+}
+
+/// One IMU sample in the jet's body frame: yaw rate and specific force (acceleration minus gravity), stamped with sensor time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImuReading {
+    pub gyro: f32,
+    pub accel: Vec2,
+    pub time: f32,
+}
+
+/// Gyro and accelerometer strapped to the jet. Each reading carries white noise on top of a slowly wandering bias.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Imu {
+    pub spec: ImuSpec,
+    gyro_bias: f32,
+    accel_bias: Vec2,
+    /// Velocity at the last sample; the accelerometer reports the average acceleration since then.
+    last_velocity: Option<Vec2>,
+    /// Sensor time of the last sample.
+    #[serde(default)]
+    last_time: f32,
+    clock: SampleClock,
+    reading: Option<ImuReading>,
+    fresh: bool,
+    rng: SimRng,
+}
+
+impl Imu {
+
+    pub fn new(spec: ImuSpec, seed: u64) -> Self {
+        Self { spec, gyro_bias: 0.0, accel_bias: Vec2::ZERO, last_velocity: None, last_time: 0.0, clock: SampleClock::default(), reading: None, fresh: false, rng: SimRng::new(seed) }
+    }
+
+    /// Latest sample, if any has been taken yet.
+    pub fn get_reading(&self) -> Option<ImuReading> {
+        return self.reading;
+    }
+
+    /// Whether the latest sample was taken during the last physics step.
+    pub fn is_fresh(&self) -> bool {
+        return self.fresh;
+    }
+
+    pub fn get_bias(&self) -> (f32, Vec2) {
+        return (self.gyro_bias, self.accel_bias);
+    }
+
+    /// Walks the biases every step and takes a sample whenever one is due.
+    pub fn sample(&mut self, physics: &Physics, handle: RigidBodyHandle, dt: f32) {
+        let spec = self.spec;
+        let walk = dt.sqrt();
+        self.gyro_bias += spec.gyro_walk * walk * self.rng.gaussian();
+        self.accel_bias += spec.accel_walk * walk * Vec2::new(self.rng.gaussian(), self.rng.gaussian());
+        self.fresh = self.clock.tick(dt, spec.rate);
+        if !self.fresh {
+            return;
+        }
+        let state = physics.get_physics_data(handle);
+        let since_sample = self.clock.time - std::mem::replace(&mut self.last_time, self.clock.time);
+        let accel = match self.last_velocity.replace(state.velocity) {
+            Some(last) if since_sample > 0.0 => (state.velocity - last) / since_sample,
+            _ => Vec2::ZERO,
+        };
+        let body_accel = Vec2::from_angle(-state.rotation).rotate(accel - physics.get_gravity());
+        let gyro = state.spin + self.gyro_bias + spec.gyro_noise * self.rng.gaussian();
+        let accel = body_accel + self.accel_bias + spec.accel_noise * Vec2::new(self.rng.gaussian(), self.rng.gaussian());
+        self.reading = Some(ImuReading { gyro, accel, time: self.clock.time });
+    }
+}
+
+/// Position sensor noise model.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GpsSpec {
+    /// Fixes per second.
+    pub rate: f32,
+    /// Standard deviation of each fix on both axes, in px.
+    pub noise: f32,
+    /// Chance that a fix is lost, 0..1.
+    pub dropout: f32,
+    /// Seconds between taking a fix and it being delivered.
+    pub latency: f32,
+}
+
+impl Default for GpsSpec {
+    fn default() -> Self {
+        Self { rate: 5.0, noise: 3.0, dropout: 0.05, latency: 0.2 }
+    }
+}
+
+/// A position fix in world space; `time` is when it was measured, not when it arrived.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GpsReading {
+    pub pos: Vec2,
+    pub time: f32,
+}
+
+/// Noisy, lossy and late position fixes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Gps {
+    pub spec: GpsSpec,
+    clock: SampleClock,
+    in_flight: VecDeque<GpsReading>,
+    reading: Option<GpsReading>,
+    fresh: bool,
+    rng: SimRng,
+}
+
+impl Gps {
+
+    pub fn new(spec: GpsSpec, seed: u64) -> Self {
+        Self { spec, clock: SampleClock::default(), in_flight: VecDeque::new(), reading: None, fresh: false, rng: SimRng::new(seed) }
+    }
+
+    /// Sensor clock, in seconds since the sensor was created.
+    pub fn get_time(&self) -> f32 {
+        return self.clock.time;
+    }
+
+    /// Latest delivered fix.
+    pub fn get_reading(&self) -> Option<GpsReading> {
+        return self.reading;
+    }
+
+    /// Whether a new fix was delivered during the last physics step.
+    pub fn is_fresh(&self) -> bool {
+        return self.fresh;
+    }
+
+    /// Takes a fix when one is due, unless it drops out, and delivers those whose latency has passed.
+    pub fn sample(&mut self, physics: &Physics, handle: RigidBodyHandle, dt: f32) {
+        let spec = self.spec;
+        if self.clock.tick(dt, spec.rate) && self.rng.next_f32() >= spec.dropout {
+            let pos = physics.get_physics_data(handle).position + spec.noise * Vec2::new(self.rng.gaussian(), self.rng.gaussian());
+            self.in_flight.push_back(GpsReading { pos, time: self.clock.time });
+        }
+        self.fresh = false;
+        while let Some(reading) = self.in_flight.front().copied() {
+            if reading.time + spec.latency > self.clock.time + 1e-6 {
+                break;
+            }
+            self.in_flight.pop_front();
+            self.reading = Some(reading);
+            self.fresh = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapier2d::na::Vector2;
+    use crate::consts::FIX_DT;
+    use crate::physics::PhysicsProperities;
+
+    /// A body pushed by a constant force has constant acceleration, so every IMU sample should report it, however
+    /// many physics steps fall between samples.
+    #[test]
+    fn accelerometer_reads_constant_force_at_uneven_rate() {
+        let mut physics = Physics::new();
+        let props = PhysicsProperities { linear_damping: 0.0, angular_damping: 0.0, ..PhysicsProperities::default() };
+        let handle = physics.add_dynamic_rigidbody(1, &Vec2::ZERO, 0.0, 0.0, 0.0);
+        physics.add_collider(handle, &Vec2::ZERO, 0.0, rapier2d::prelude::SharedShape::ball(5.0), props);
+        let force = Vec2::new(300.0, -120.0);
+        let rb = physics.rigid_bodies.get_mut(handle).unwrap();
+        rb.add_force(Vector2::new(force.x, force.y), true);
+        let expected = force / rb.mass();
+        let spec = ImuSpec { rate: 20.0, gyro_noise: 0.0, gyro_walk: 0.0, accel_noise: 0.0, accel_walk: 0.0 };
+        let mut imu = Imu::new(spec, 0);
+        let mut samples = 0;
+        for _ in 0..90 {
+            physics.step_physics();
+            imu.sample(&physics, handle, FIX_DT);
+            if !imu.is_fresh() {
+                continue;
+            }
+            samples += 1;
+            if samples == 1 {
+                continue;
+            }
+            let accel = imu.get_reading().unwrap().accel;
+            assert!(accel.distance(expected) < expected.length() * 0.01, "read {:?}, expected {:?}", accel, expected);
+        }
+        assert!((59..=61).contains(&samples), "{} samples in 3 s at 20 Hz", samples);
+    }
+}
@@ -3,168 +3,445 @@
 use macroquad::prelude::*;
 use rapier2d::prelude::*;
 use crate::collector::Collector;
-use crate::consts::WORLD_H;
-use crate::consts::WORLD_W;
+use crate::consts::*;
 use crate::physics::*;
-use crate::camera::*;
+use crate::rng::SimRng;
 use crate::util::*;
 use crate::unit::*;
 use crate::jet::*;
+use crate::replay::*;
+use crate::events::*;
+use crate::entity::*;
+use crate::mission::MissionEvent;
+use crate::scene::{SceneDef, SceneError};
+use serde::{Deserialize, Serialize};
 
+/// Longest frame the accumulator will swallow; anything slower is dropped instead of spiralling into catch-up steps.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// What happens at the edges of the `WORLD_W` x `WORLD_H` rectangle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorldBoundary {
+    /// No edges; bodies fly off into empty space.
+    #[default]
+    Open,
+    /// Static walls on all four edges.
+    Walled,
+    /// Bodies leaving one edge re-enter on the opposite one with their velocities intact.
+    Toroidal,
+}
+
+impl WorldBoundary {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "open" => Some(WorldBoundary::Open),
+            "walled" => Some(WorldBoundary::Walled),
+            "toroidal" => Some(WorldBoundary::Toroidal),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WorldBoundary::Open => "open",
+            WorldBoundary::Walled => "walled",
+            WorldBoundary::Toroidal => "toroidal",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            WorldBoundary::Open => WorldBoundary::Walled,
+            WorldBoundary::Walled => WorldBoundary::Toroidal,
+            WorldBoundary::Toroidal => WorldBoundary::Open,
+        }
+    }
+}
+
+/// Body key given to boundary and scene walls, which are not entities; contact events report them under it.
+pub const WALL_KEY: u64 = 0;
+
+/// Headless world: owns physics and every entity, knows nothing about windows, cameras or input devices.
+#[derive(Serialize, Deserialize)]
 pub struct Simulation {
     pub physics: Physics,
-    pub camera: Camera2D,
-    pub units: Collector,
-    pub selected_unit: Option<u64>,
-    pub joint: Option<ImpulseJointHandle>,
-    pub jet: Vec<Jet>,
+    pub entities: Collector,
+    #[serde(default)]
+    joints: Vec<ImpulseJointHandle>,
+    #[serde(default)]
+    boundary: WorldBoundary,
+    #[serde(default)]
+    walls: Vec<RigidBodyHandle>,
+    #[serde(default)]
+    boundary_walls: Vec<RigidBodyHandle>,
+    pub rng: SimRng,
+    seed: u64,
+    accumulator: f32,
+    step_count: u64,
+    #[serde(default)]
+    events: Vec<SimEvent>,
+    #[serde(default)]
+    pending_despawn: Vec<u64>,
+    #[serde(skip)]
+    pub recorder: Option<Recorder>,
+    #[serde(skip)]
+    pub playback: Option<Playback>,
 }
 
 impl Simulation {
 
     pub fn new() -> Self {
+        return Self::with_seed(SimRng::time_seed());
+    }
+
+    /// Builds an empty world whose every random decision comes from `seed`: same seed and same inputs give bit-identical runs.
+    pub fn with_seed(seed: u64) -> Self {
         Self {
             physics: Physics::new(),
-            camera: create_camera(),
-            units: Collector::new(),
-            selected_unit: None,
-            joint: None,
-            jet: vec![],
+            entities: Collector::new(),
+            joints: vec![],
+            boundary: WorldBoundary::Open,
+            walls: vec![],
+            boundary_walls: vec![],
+            rng: SimRng::new(seed),
+            seed,
+            accumulator: 0.0,
+            step_count: 0,
+            events: vec![],
+            pending_despawn: vec![],
+            recorder: None,
+            playback: None,
         }
     }
 
+    /// Fresh world seeded with `seed` and populated from `scene`.
+    pub fn from_scene(seed: u64, scene: &SceneDef) -> Result<Self, SceneError> {
+        let mut sim = Self::with_seed(seed);
+        scene.build(&mut sim)?;
+        return Ok(sim);
+    }
+
+    /// Builds the built-in default scene.
     pub fn init(&mut self) {
-        let hull = vec![
-            Vec2::new(15.0, 0.0),
-            Vec2::new(5.0, -5.0),
-            Vec2::new(0.0, -10.0),
-            Vec2::new(-3.0, -11.0),
-            Vec2::new(-8.0, -5.0),
-            Vec2::new(-10.0, -6.0),
-            Vec2::new(-10.0, 0.0),
-            Vec2::new(-10.0, 6.0),
-            Vec2::new(-8.0, 5.0),
-            Vec2::new(-3.0, 11.0),
-            Vec2::new(0.0, 10.0),
-            Vec2::new(5.0, 5.0),
-        ];
-        let jet = Jet::new_dyn_poly(hull, Vec2::new(600.0, 400.0), &mut self.physics);
-        self.jet.push(jet);
-
-        //let s1 = Unit::new_static_rect([20.0, 900.0], Vec2::new(0.0, 450.0), &mut self.physics);
-        //self.units.add_unit(s1, &mut self.physics);
-        //let s2 = Unit::new_static_rect([20.0, 900.0], Vec2::new(1200.0, 450.0), &mut self.physics);
-        //self.units.add_unit(s2, &mut self.physics);
-        //let s3 = Unit::new_static_rect([1200.0, 20.0], Vec2::new(600.0, 0.0), &mut self.physics);
-        //self.units.add_unit(s3, &mut self.physics);
-        //let s4 = Unit::new_static_rect([1200.0, 20.0], Vec2::new(600.0, 900.0), &mut self.physics);
-        //self.units.add_unit(s4, &mut self.physics);
-        //for _ in 0..64 {
-        //    let pos = random_position(1000.0, 800.0);
-        //    let verts = vec![Vec2::new(5.0, 5.0)*3.0, Vec2::new(-2.0, 6.0)*3.0, Vec2::new(-5.0, 5.0)*3.0, Vec2::new(2.0, -3.0)*3.0, Vec2::new(-5.0, -5.0)*3.0, Vec2::new(5.0, -5.0)*3.0];
-        //    let poly = Unit::new_dyn_poly(verts, pos, &mut self.physics);
-        //    self.units.add_unit(poly, &mut self.physics);
-        //}
-        self.units.add_many_units(48, &mut self.physics);
-        //let ball01 = Unit::new_dyn_circle(25.0, Vec2::new(600.0, 600.0), &mut self.physics);
-        //let ball02 = Unit::new_dyn_circle(25.0, Vec2::new(650.0, 650.0), &mut self.physics);
-        //let static_obj = Unit::new_static_rect([800.0, 10.0], Vec2::new(600.0, 40.0), &mut self.physics);
-        //let static_obj2 = Unit::new_static_rect([25.0, 25.0], Vec2::new(600.0, 720.0), &mut self.physics);
-        //let joint = PrismaticJointBuilder::new(Vector::y_axis()).local_anchor1(point![0.0, 0.0]).local_anchor2(point![0.0, 0.0]).limits([10.0, 300.0]).motor_velocity(0.0, 0.0).build();
-        //self.joint = Some(self.physics.impulse_joint_set.insert(ball02.physics_handles[0], ball01.physics_handles[0], joint, true));
-        //self.units.add_unit(ball01, &mut self.physics);
-        //self.units.add_unit(ball02, &mut self.physics);
-        //self.units.add_unit(static_obj, &mut self.physics);
-        //self.units.add_unit(static_obj2, &mut self.physics);
-        //for _ in 0..14 {
-        //    self.units.add_complex(&mut self.physics);
-        //}
-    }
-
-    pub fn update(&mut self) {
-        self.update_units();
-        for jet in self.jet.iter_mut() {
-            jet.update(get_frame_time(), &mut self.physics);
-        }
+        let scene = SceneDef::default_scene();
+        scene.build(self).expect("built-in scene is valid");
     }
 
-    pub fn step_physics(&mut self) {
-        self.physics.step_physics();
+    /// Adds a unit, jet or any other entity whose rigid body already lives in `physics`.
+    pub fn spawn(&mut self, object: impl Into<Object>) -> u64 {
+        return self.entities.add(object);
+    }
+
+    /// Adds a static rectangular wall centered at `center`, rotated by `rotation`.
+    pub fn add_wall(&mut self, center: Vec2, half_extents: Vec2, rotation: f32, props: PhysicsProperities) -> RigidBodyHandle {
+        let shape = SharedShape::cuboid(half_extents.x, half_extents.y);
+        let wall = self.physics.add_static(WALL_KEY, &center, rotation, shape, props);
+        self.walls.push(wall);
+        return wall;
+    }
+
+    /// Connects the bodies of two entities with an impulse joint.
+    pub fn add_joint(&mut self, key1: u64, key2: u64, joint: impl Into<GenericJoint>) -> Option<ImpulseJointHandle> {
+        let body1 = self.get_body_handle(key1)?;
+        let body2 = self.get_body_handle(key2)?;
+        let handle = self.physics.impulse_joint_set.insert(body1, body2, joint, true);
+        self.joints.push(handle);
+        return Some(handle);
     }
 
-    pub fn update_units(&mut self) {
-        let dt = get_frame_time();
-        for (_, unit) in self.units.get_iter_mut() {
-            unit.update(dt, &mut self.physics);
+    pub fn get_joints(&self) -> &[ImpulseJointHandle] {
+        return &self.joints;
+    }
+
+    pub fn get_body_handle(&self, key: u64) -> Option<RigidBodyHandle> {
+        return self.entities.get(key).map(|entity| entity.body().physics_handle);
+    }
+
+    /// Feeds real elapsed time into the accumulator and runs as many fixed `FIX_DT` steps as it covers.
+    /// Returns the number of physics steps taken.
+    pub fn advance(&mut self, frame_dt: f32) -> u32 {
+        return self.advance_with(frame_dt, |_| {});
+    }
+
+    /// Like `advance`, calling `each_step` after every step so none of the steps' events are missed.
+    pub fn advance_with(&mut self, frame_dt: f32, mut each_step: impl FnMut(&Simulation)) -> u32 {
+        self.accumulator += frame_dt.min(MAX_FRAME_TIME);
+        let mut steps = 0;
+        while self.accumulator >= FIX_DT {
+            self.step();
+            each_step(self);
+            self.accumulator -= FIX_DT;
+            steps += 1;
         }
+        return steps;
     }
 
-    pub fn draw(&self) {
-        set_camera(&self.camera);
-        clear_background(BLACK);
-        for jet in self.jet.iter() {
-            jet.draw();
-        }        
-        //match self.joint {
-        //    None => {},
-        //    Some(joint_handle) => {
-        //        let joint = self.physics.impulse_joint_set.get(joint_handle).unwrap();
-        //        let rb1 = self.physics.rigid_bodies.get(joint.body1).unwrap();
-        //        let rb2 = self.physics.rigid_bodies.get(joint.body2).unwrap();
-        //        let (p1, r1) = iso_to_vec2_rot(rb1.position());
-        //        let (p2, r2) = iso_to_vec2_rot(rb1.position());
-        //        draw_line(p1.x, p1.y, p2.x, p2.y, 2.0, RED);
-        //    }
-        //}
-        self.draw_units();
+    /// Advances the world by exactly one `FIX_DT` physics step.
+    pub fn step(&mut self) {
+        let replay_finished = self.feed_playback();
+        self.update(FIX_DT);
+        self.step_physics();
+        self.sync_physics();
+        if self.boundary == WorldBoundary::Toroidal {
+            self.wrap_entities();
+        }
+        self.dispatch_events();
+        self.report_overruns();
+        if replay_finished {
+            self.events.push(SimEvent::ReplayFinished { step: self.step_count });
+        }
+        self.sense(FIX_DT);
+        self.run_missions(FIX_DT);
+        self.flush_despawns();
+        self.step_count += 1;
+    }
+
+    /// Schedules a unit or jet for removal at the end of the current step, so it is safe to call while iterating the world.
+    pub fn despawn(&mut self, key: u64) {
+        if !self.pending_despawn.contains(&key) {
+            self.pending_despawn.push(key);
+        }
     }
 
-    pub fn draw_units(&self) {
-        let dt = get_frame_time();
-        for (_, unit) in self.units.get_iter() {
-            unit.draw();
+    /// Removes queued entities with their bodies, colliders and joints, and reports a `Despawned` event for each.
+    fn flush_despawns(&mut self) {
+        for key in std::mem::take(&mut self.pending_despawn) {
+            let Some(object) = self.entities.remove(key) else {
+                continue;
+            };
+            let joints = self.physics.remove_physics_object(object.entity().body().physics_handle);
+            self.joints.retain(|joint| !joints.contains(joint));
+            for entity in self.entities.get_iter_mut() {
+                entity.body_mut().contacts.forget(key);
+            }
+            self.events.push(SimEvent::Despawned { key });
         }
     }
 
-    pub fn input(&mut self) {
-        self.mouse_input();
-        control_camera(&mut self.camera);
-        self.keyboard();
+    pub fn get_boundary(&self) -> WorldBoundary {
+        return self.boundary;
     }
 
-    fn keyboard(&mut self) {
-        let jet = self.jet.first_mut().unwrap();
-        if is_key_pressed(KeyCode::W) {
-            jet.go(1.0);
-            println!("go");
+    /// Switches the boundary mode, building or tearing down the edge walls as needed.
+    pub fn set_boundary(&mut self, boundary: WorldBoundary) {
+        for wall in self.boundary_walls.drain(..) {
+            self.physics.remove_physics_object(wall);
         }
-        if is_key_pressed(KeyCode::S) {
-            jet.go(-1.0);
-            println!("back");
+        self.boundary = boundary;
+        if boundary == WorldBoundary::Walled {
+            let t = WALL_THICKNESS / 2.0;
+            let edges = [
+                (Vec2::new(-t, WORLD_H / 2.0), Vec2::new(t, WORLD_H / 2.0 + 2.0 * t)),
+                (Vec2::new(WORLD_W + t, WORLD_H / 2.0), Vec2::new(t, WORLD_H / 2.0 + 2.0 * t)),
+                (Vec2::new(WORLD_W / 2.0, -t), Vec2::new(WORLD_W / 2.0 + 2.0 * t, t)),
+                (Vec2::new(WORLD_W / 2.0, WORLD_H + t), Vec2::new(WORLD_W / 2.0 + 2.0 * t, t)),
+            ];
+            for (pos, half) in edges {
+                let props = PhysicsProperities::default().with_layer(Layer::Terrain);
+                let wall = self.physics.add_static(WALL_KEY, &pos, 0.0, SharedShape::cuboid(half.x, half.y), props);
+                self.boundary_walls.push(wall);
+            }
         }
-        if is_key_pressed(KeyCode::A) {
-            jet.turn_left();
-            println!("left");
+    }
+
+    /// Scene walls and boundary walls as (center, half extents, rotation), for drawing.
+    pub fn get_walls(&self) -> Vec<(Vec2, Vec2, f32)> {
+        let mut walls = vec![];
+        for handle in self.walls.iter().chain(self.boundary_walls.iter()) {
+            let rb = self.physics.rigid_bodies.get(*handle).unwrap();
+            let collider = self.physics.colliders.get(rb.colliders()[0]).unwrap();
+            let half = collider.shape().as_cuboid().unwrap().half_extents;
+            walls.push((matrix_to_vec2(rb.position().translation), Vec2::new(half.x, half.y), rb.rotation().angle()));
         }
-        if is_key_pressed(KeyCode::D) {
-            jet.turn_right();
-            println!("right");
+        return walls;
+    }
+
+    /// Moves every entity that crossed an edge to the opposite side, shifting its previous state along so drawing doesn't streak across the world.
+    fn wrap_entities(&mut self) {
+        for entity in self.entities.get_iter_mut() {
+            let body = entity.body_mut();
+            let wrapped = wrap_around(&body.pos);
+            if wrapped != body.pos {
+                body.relocate(wrapped, &mut self.physics);
+            }
         }
     }
 
-    fn mouse_input(&mut self) {
-        if is_mouse_button_released(MouseButton::Left) {
-            self.selected_unit = None;
-            let (mouse_posx, mouse_posy) = mouse_position();
-            let mouse_pos = Vec2::new(mouse_posx, mouse_posy);
-            let rel_coords = self.camera.screen_to_world(mouse_pos);
-            for (id, unit) in self.units.get_iter() {
-                if contact_mouse(rel_coords, unit.pos, unit.size) {
-                    self.selected_unit = Some(*id);
-                    break;
+    /// Events produced by the most recent physics step.
+    pub fn step_events(&self) -> &[SimEvent] {
+        return &self.events;
+    }
+
+    /// Contacts currently touching the entity with `key`.
+    pub fn get_contacts(&self, key: u64) -> Option<&Contacts> {
+        return self.entities.get(key).map(|entity| &entity.body().contacts);
+    }
+
+    fn dispatch_events(&mut self) {
+        for entity in self.entities.get_iter_mut() {
+            entity.body_mut().contacts.clear_forces();
+        }
+        let events = self.physics.take_events();
+        for event in events.iter() {
+            let Some((a, b)) = event.contact_pair() else {
+                continue;
+            };
+            for (this, other) in [(a, b), (b, a)] {
+                if let Some(entity) = self.entities.get_mut(this) {
+                    entity.body_mut().contacts.apply(event, other);
                 }
             }
         }
+        self.events = events;
+    }
+
+    fn report_overruns(&mut self) {
+        for jet in self.entities.jets_mut() {
+            if let Some(elapsed) = jet.take_overrun() {
+                self.events.push(SimEvent::ControlOverrun { jet: jet.body.key, elapsed });
+            }
+        }
+    }
+
+    fn sense(&mut self, dt: f32) {
+        for jet in self.entities.jets_mut() {
+            jet.sense(&self.physics, dt);
+        }
+    }
+
+    /// Steps every jet's mission on the fresh physics state and reports its progress as events.
+    fn run_missions(&mut self, dt: f32) {
+        for jet in self.entities.jets_mut() {
+            let jet_key = jet.body.key;
+            for event in jet.run_mission(dt, &self.physics) {
+                self.events.push(match event {
+                    MissionEvent::Reached(index) => SimEvent::WaypointReached { jet: jet_key, index },
+                    MissionEvent::Complete => SimEvent::MissionComplete { jet: jet_key },
+                });
+            }
+        }
+    }
+
+    /// Fraction of a step left in the accumulator, used to blend the last two physics states when drawing.
+    pub fn alpha(&self) -> f32 {
+        return self.accumulator / FIX_DT;
+    }
+
+    /// Issues a control command to a jet, recording it against the current step if a recorder is attached.
+    /// Returns false if there is no jet with `jet_key`.
+    pub fn command(&mut self, jet_key: u64, command: Command) -> bool {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(ControlEvent { step: self.step_count, jet: jet_key, command });
+        }
+        let Some(jet) = self.get_jet_mut(jet_key) else {
+            return false;
+        };
+        jet.apply_command(command);
+        return true;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        return self.playback.is_some();
+    }
+
+    /// Issues the replayed commands due this step. Returns true when the replay has just run out.
+    fn feed_playback(&mut self) -> bool {
+        let Some(mut playback) = self.playback.take() else {
+            return false;
+        };
+        for event in playback.due(self.step_count) {
+            self.command(event.jet, event.command);
+        }
+        if playback.is_finished(self.step_count) {
+            return true;
+        }
+        self.playback = Some(playback);
+        return false;
+    }
+
+    pub fn get_jet(&self, key: u64) -> Option<&Jet> {
+        return self.entities.get_object(key).and_then(|object| object.as_jet());
+    }
+
+    pub fn get_jet_mut(&mut self, key: u64) -> Option<&mut Jet> {
+        return self.entities.get_object_mut(key).and_then(|object| object.as_jet_mut());
+    }
+
+    pub fn get_jets(&self) -> impl Iterator<Item = &Jet> {
+        return self.entities.jets();
     }
 
-}
\ No newline at end of file
+    pub fn get_seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    pub fn get_step_count(&self) -> u64 {
+        return self.step_count;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for entity in self.entities.get_iter_mut() {
+            entity.update(dt, &mut self.physics);
+        }
+    }
+
+    pub fn step_physics(&mut self) {
+        self.physics.step_physics();
+    }
+
+    fn sync_physics(&mut self) {
+        for entity in self.entities.get_iter_mut() {
+            entity.sync(&self.physics);
+        }
+    }
+
+}
+
+/// Every body's handle, position, rotation and velocities, for exact comparison between runs in tests.
+#[cfg(test)]
+pub(crate) fn body_states(sim: &Simulation) -> Vec<(RigidBodyHandle, [f32; 6])> {
+    return sim.physics.rigid_bodies.iter().map(|(handle, rb)| {
+        let pos = rb.translation();
+        let vel = rb.linvel();
+        (handle, [pos.x, pos.y, rb.rotation().angle(), vel.x, vel.y, rb.angvel()])
+    }).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_runs_are_identical() {
+        let scene = SceneDef::default_scene();
+        let mut a = Simulation::from_scene(11, &scene).unwrap();
+        let mut b = Simulation::from_scene(11, &scene).unwrap();
+        for _ in 0..60 {
+            a.step();
+            b.step();
+        }
+        assert_eq!(a.get_step_count(), 60);
+        assert_eq!(body_states(&a), body_states(&b));
+        let mut c = Simulation::from_scene(12, &scene).unwrap();
+        for _ in 0..60 {
+            c.step();
+        }
+        assert_ne!(body_states(&a), body_states(&c));
+    }
+
+    #[test]
+    fn accumulator_runs_whole_fixed_steps() {
+        let mut sim = Simulation::with_seed(0);
+        assert_eq!(sim.advance(FIX_DT * 0.6), 0);
+        assert!((sim.alpha() - 0.6).abs() < 1e-4);
+        assert_eq!(sim.advance(FIX_DT * 0.6), 1);
+        assert!((sim.alpha() - 0.2).abs() < 1e-4);
+        assert_eq!(sim.advance(FIX_DT * 2.0), 2);
+        assert_eq!(sim.get_step_count(), 3);
+        // A long stall is clamped to MAX_FRAME_TIME rather than caught up step by step.
+        let steps = sim.advance(10.0);
+        assert_eq!(steps, ((MAX_FRAME_TIME + 0.2 * FIX_DT) / FIX_DT) as u32);
+        assert!(sim.alpha() < 1.0);
+    }
+}
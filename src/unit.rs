@@ -9,24 +9,100 @@ use crate::consts::*;
 use crate::util::*;
 use crate::physics::*;
 use crate::collector::*;
+use crate::rng::SimRng;
+use crate::entity::*;
 use macroquad::{color, prelude::*};
-use macroquad::rand::*;
 use rapier2d::geometry::*;
 use rapier2d::na::Vector2;
 use rapier2d::prelude::{RigidBody, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
 
 
+/// Outline of a body part, in px, around the part's own origin.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PartShape {
+    Ball { radius: f32 },
+    Cuboid { half_extents: Vec2 },
+    /// Convex hull of the points; at least three that aren't in a line.
+    Convex { points: Vec<Vec2> },
+}
+
+impl PartShape {
+
+    /// Radius of a circle around the part's origin that encloses it.
+    pub fn get_radius(&self) -> f32 {
+        match self {
+            PartShape::Ball { radius } => *radius,
+            PartShape::Cuboid { half_extents } => half_extents.length(),
+            PartShape::Convex { points } => points.iter().fold(0.0, |r, p| r.max(p.length())),
+        }
+    }
+
+    /// The same outline grown or shrunk by `factor`.
+    pub fn scaled(&self, factor: f32) -> Self {
+        match self {
+            PartShape::Ball { radius } => PartShape::Ball { radius: radius * factor },
+            PartShape::Cuboid { half_extents } => PartShape::Cuboid { half_extents: *half_extents * factor },
+            PartShape::Convex { points } => PartShape::Convex { points: points.iter().map(|p| *p * factor).collect() },
+        }
+    }
+
+    /// Collision shape, or `None` if a convex part has no proper hull.
+    pub fn to_shape(&self) -> Option<SharedShape> {
+        match self {
+            PartShape::Ball { radius } => Some(SharedShape::ball(*radius)),
+            PartShape::Cuboid { half_extents } => Some(SharedShape::cuboid(half_extents.x, half_extents.y)),
+            PartShape::Convex { points } => convex_hull_shape(points),
+        }
+    }
+}
+
+/// One part of a unit blueprint, placed relative to the unit's origin.
+#[derive(Clone)]
 pub struct PartDef {
-    pub shape_size: f32,
-    pub relative_position: Vec2,
+    pub shape: PartShape,
+    pub offset: Vec2,
+    pub rot: f32,
+    /// Omitted means the unit's color.
+    pub color: Option<Color>,
+    /// Omitted means the unit's material. The unit's layer and ignore mask apply either way.
+    pub material: Option<PhysicsProperities>,
 }
 
+impl PartDef {
+
+    pub fn new(shape: PartShape, offset: Vec2) -> Self {
+        Self { shape, offset, rot: 0.0, color: None, material: None }
+    }
+
+    pub fn rotated(mut self, rot: f32) -> Self {
+        self.rot = rot;
+        return self;
+    }
+
+    pub fn colored(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        return self;
+    }
+
+    pub fn with_material(mut self, material: PhysicsProperities) -> Self {
+        self.material = Some(material);
+        return self;
+    }
+}
+
+/// Blueprint of a composite unit; every part becomes a collider of one rigid body.
+#[derive(Clone, Default)]
 pub struct PartsScheme {
     pub parts: Vec<PartDef>
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct BodyPart {
     pub rel_pos: Vec2,
+    #[serde(default)]
+    pub rel_rot: f32,
+    #[serde(with = "serde_color")]
     pub color: Color,
     pub shape: SharedShape,
     handle: Option<ColliderHandle>,
@@ -34,19 +110,36 @@ pub struct BodyPart {
 
 impl BodyPart {
     pub fn add_new(relative_position: Vec2, size: f32, color: Color) -> Self {
+        return Self::with_shape(relative_position, 0.0, SharedShape::ball(size), color);
+    }
+
+    pub fn with_shape(relative_position: Vec2, relative_rotation: f32, shape: SharedShape, color: Color) -> Self {
         Self {
             color,
             rel_pos: relative_position,
-            shape: SharedShape::ball(size),
+            rel_rot: relative_rotation,
+            shape,
             handle: None,
         }
     }
 
-    pub fn draw_circle(&self, position: &Vec2, rot: f32) {
+    /// Draws the part filled, for a unit at `position` turned by `rot`.
+    pub fn draw(&self, position: &Vec2, rot: f32) {
         let mut pos = Vec2::from_angle(rot).rotate(self.rel_pos);
-        pos += position.clone();
-        let size = self.shape.as_ball().unwrap().radius;
-        draw_circle(pos.x, pos.y, size, self.color); 
+        pos += *position;
+        let rot = rot + self.rel_rot;
+        if let Some(ball) = self.shape.as_ball() {
+            draw_circle(pos.x, pos.y, ball.radius, self.color);
+        } else if let Some(cuboid) = self.shape.as_cuboid() {
+            let size = cuboid.half_extents * 2.0;
+            let params = DrawRectangleParams { offset: Vec2::new(0.5, 0.5), rotation: rot, color: self.color };
+            draw_rectangle_ex(pos.x, pos.y, size.x, size.y, params);
+        } else if let Some(polygon) = self.shape.as_convex_polygon() {
+            let points: Vec<Vec2> = polygon.points().iter().map(|p| pos + Vec2::from_angle(rot).rotate(Vec2::new(p.x, p.y))).collect();
+            for i in 1..points.len().saturating_sub(1) {
+                draw_triangle(points[0], points[i], points[i + 1], self.color);
+            }
+        }
     }
 
     pub fn get_rel_position(&self) -> Vec2 {
@@ -70,74 +163,106 @@ impl BodyPart {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Unit {
-    pub key: u64,
-    pub pos: Vec2,
-    pub rot: f32,
+    pub body: Body,
+    /// Radius of the core ball; zero for units built from a `PartsScheme`, which have no core.
     pub size: f32,
-    pub color: color::Color,
-    pub shape: SharedShape,
-    pub physics_handle: RigidBodyHandle,
     pub data: Vec<(f32, Vec2, f32)>,
     pub body_parts: Vec<BodyPart>,
-    
 }
 
 impl Unit {
     
-    pub fn new(physics: &mut Physics) -> Self {
-        let size = rand::gen_range(SIZE_MIN, SIZE_MAX) as f32;
+    pub fn new(physics: &mut Physics, rng: &mut SimRng) -> Self {
+        return Self::with_properties(physics, rng, PhysicsProperities::default());
+    }
+
+    /// Random three-ball unit spawned with the given material; its parts share the material's layer and ignore mask.
+    pub fn with_properties(physics: &mut Physics, rng: &mut SimRng, props: PhysicsProperities) -> Self {
+        let size = rng.gen_range_u32(SIZE_MIN, SIZE_MAX) as f32;
+        let color = random_color(rng);
+        let pos = random_position(WORLD_W, WORLD_H, rng);
+        return Self::build(pos, 0.0, size, color, props, physics, rng);
+    }
+
+    /// Three-ball unit with explicit placement, size and color.
+    pub fn build(pos: Vec2, rot: f32, size: f32, color: Color, props: PhysicsProperities, physics: &mut Physics, rng: &mut SimRng) -> Self {
         let shape = SharedShape::ball(size);
-        let color = random_color();
         let mut parts: Vec<BodyPart> = vec![];
         let step = 2.0*PI/3.0;
-        let key = gen_range(u64::MIN, u64::MAX);
-        let pos = random_position(WORLD_W as f32, WORLD_H as f32);
-        let rbh = physics.add_dynamic(key, &pos, 0.0, shape.clone(), PhysicsProperities::default());
+        let key = rng.next_u64();
+        let part_props = PhysicsProperities { layer: props.layer, ignore: props.ignore, ..PhysicsProperities::free() };
+        let rbh = physics.add_dynamic(key, &pos, rot, shape.clone(), props, rng);
         for i in 0..3 {
             let rel_pos = Vec2::from_angle(i as f32 * step) * 2.0*size;
             let mut part = BodyPart::add_new(rel_pos, size, color);
-            let coll_handle = physics.add_collider(rbh, &rel_pos, 0.0, part.get_shape(), PhysicsProperities::free());
+            let coll_handle = physics.add_collider(rbh, &rel_pos, 0.0, part.get_shape(), part_props.clone());
             part.set_collider_handle(coll_handle);
             parts.push(part);
         }
         Self {
-            key,
-            pos,
-            //rot: random_rotation(),
-            rot: 0.0,
+            body: Body::new(key, pos, rot, color, shape, rbh),
             size,
-            color,
-            shape: shape,
-            physics_handle: rbh,
             data: vec![],
             body_parts: parts,
         }
     }
 
-    pub fn draw(&self) {
-        let x0 = self.pos.x;
-        let y0 = self.pos.y;
-        for part in self.body_parts.iter() {
-            part.draw_circle(&self.pos, self.rot);
+    /// Unit made of the scheme's parts, each its own collider with its own shape, placement, color and material.
+    /// Parts without a color or material take `color` and `props`; convex parts without a proper hull are left out.
+    pub fn from_scheme(scheme: &PartsScheme, pos: Vec2, rot: f32, color: Color, props: PhysicsProperities, physics: &mut Physics, rng: &mut SimRng) -> Self {
+        let key = rng.next_u64();
+        let rbh = physics.add_dynamic_rigidbody(key, &pos, rot, props.linear_damping, props.angular_damping);
+        let mut parts: Vec<BodyPart> = vec![];
+        let mut compound = vec![];
+        for def in scheme.parts.iter() {
+            let Some(shape) = def.shape.to_shape() else {
+                continue;
+            };
+            let material = def.material.clone().unwrap_or_else(|| props.clone());
+            let part_props = PhysicsProperities { layer: props.layer, ignore: props.ignore, ..material };
+            let mut part = BodyPart::with_shape(def.offset, def.rot, shape.clone(), def.color.unwrap_or(color));
+            let coll_handle = physics.add_collider(rbh, &def.offset, def.rot, shape.clone(), part_props);
+            part.set_collider_handle(coll_handle);
+            parts.push(part);
+            compound.push((make_isometry(def.offset.x, def.offset.y, def.rot), shape));
         }
-        draw_circle(x0, y0, self.size, RED);
-    }    
+        physics.kick(rbh, rng);
+        let shape = match compound.is_empty() {
+            true => SharedShape::ball(1.0),
+            false => SharedShape::compound(compound),
+        };
+        Self {
+            body: Body::new(key, pos, rot, color, shape, rbh),
+            size: 0.0,
+            data: vec![],
+            body_parts: parts,
+        }
+    }
+}
 
-    pub fn update(&mut self, dt: f32, physics: &mut Physics) {
-        self.update_physics(physics);
+impl Entity for Unit {
+
+    fn body(&self) -> &Body {
+        return &self.body;
+    }
+
+    fn body_mut(&mut self) -> &mut Body {
+        return &mut self.body;
     }
 
-    fn draw_circle(&self) {
-        let x0 = self.pos.x;
-        let y0 = self.pos.y;
-        draw_circle_lines(x0, y0, self.size, 3.0, self.color);
-        //self.draw_front();
+    fn draw_at(&self, pos: Vec2, rot: f32) {
+        for part in self.body_parts.iter() {
+            part.draw(&pos, rot);
+        }
+        if self.size > 0.0 {
+            draw_circle(pos.x, pos.y, self.size, RED);
+        }
     }
 
-    fn update_physics(&mut self, physics: &mut Physics) {
-        let physics_data = physics.get_physics_data(self.physics_handle);
-        self.pos = physics_data.position;
-        self.rot = physics_data.rotation;
+    /// Radius of a circle that encloses every body part.
+    fn get_extent(&self) -> f32 {
+        return self.body_parts.iter().fold(self.size, |ext, part| ext.max(part.rel_pos.length() + part.shape.compute_local_bounding_sphere().radius));
     }
 }
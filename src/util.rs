@@ -2,43 +2,44 @@
 
 use std::f32::consts::PI;
 use crate::consts::*;
+use crate::rng::SimRng;
 use macroquad::{color, prelude::*};
 use rapier2d::prelude::*;
 use rapier2d::parry::query::contact; 
 use rapier2d::na::{Isometry2, Vector2, Translation, Point2};
 
-pub fn random_unit() -> f32 {
-    return rand::gen_range(-1.0, 1.0);
+pub fn random_unit(rng: &mut SimRng) -> f32 {
+    return rng.gen_range(-1.0, 1.0);
 }
 
-pub fn random_position(x_max: f32, y_max: f32) -> Vec2 {
-    let x = rand::gen_range(0.0, x_max);
-    let y = rand::gen_range(0.0, y_max);
+pub fn random_position(x_max: f32, y_max: f32, rng: &mut SimRng) -> Vec2 {
+    let x = rng.gen_range(0.0, x_max);
+    let y = rng.gen_range(0.0, y_max);
     return Vec2::new(x, y);
 }
 
-pub fn random_rotation() -> f32 {
-    let rot = rand::gen_range(0.0, PI * 2.0);
+pub fn random_rotation(rng: &mut SimRng) -> f32 {
+    let rot = rng.gen_range(0.0, PI * 2.0);
     return rot;
 }
 
-pub fn random_unit_vec2() -> Vec2 {
-    let x = rand::gen_range(-1.0, 1.0);
-    let y = rand::gen_range(-1.0, 1.0);
+pub fn random_unit_vec2(rng: &mut SimRng) -> Vec2 {
+    let x = rng.gen_range(-1.0, 1.0);
+    let y = rng.gen_range(-1.0, 1.0);
     return Vec2::new(x, y).normalize_or_zero();
 }
 
-pub fn random_color() -> color::Color {
-    let colors = vec![RED, GREEN, BLUE, YELLOW, ORANGE, GRAY, SKYBLUE, LIME];
+pub fn random_color(rng: &mut SimRng) -> color::Color {
+    let colors = [RED, GREEN, BLUE, YELLOW, ORANGE, GRAY, SKYBLUE, LIME];
     let num = colors.len();
-    let c = rand::gen_range(0, num);
+    let c = rng.gen_index(num);
     return colors[c];
 }
 
-pub fn random_color5() -> color::Color {
+pub fn random_color5(rng: &mut SimRng) -> color::Color {
     let colors = [RED, BLUE, GREEN, YELLOW, WHITE];
     //let num = colors.len();
-    let c = rand::gen_range(0, 5);
+    let c = rng.gen_index(5);
     return colors[c];
 }
 
@@ -48,8 +49,24 @@ pub fn angle2vec2(angle: f32) -> Vec2 {
     return v;
 }
 
+/// Signed turn from `from` to `to` along the shorter arc, in -PI..PI.
+pub fn angle_diff(from: f32, to: f32) -> f32 {
+    let mut delta = (to - from) % (2.0 * PI);
+    if delta > PI {
+        delta -= 2.0 * PI;
+    } else if delta < -PI {
+        delta += 2.0 * PI;
+    }
+    return delta;
+}
+
+/// Interpolates between two angles along the shorter arc.
+pub fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    return from + angle_diff(from, to) * t;
+}
+
 pub fn wrap_around(v: &Vec2) -> Vec2 {
-    let tolerance = 5.0;
+    let tolerance = WRAP_MARGIN;
     let mut vr = Vec2::new(v.x, v.y);
     if vr.x > WORLD_W + tolerance {
         vr.x = 0.0 - tolerance;
@@ -64,6 +81,30 @@ pub fn wrap_around(v: &Vec2) -> Vec2 {
     return vr;
 }
 
+/// Shifts at which a toroidal copy of something at `pos` within `margin` of a seam is visible, always starting with `Vec2::ZERO`.
+pub fn seam_offsets(pos: Vec2, margin: f32) -> Vec<Vec2> {
+    let period = Vec2::new(WORLD_W + 2.0 * WRAP_MARGIN, WORLD_H + 2.0 * WRAP_MARGIN);
+    let mut xs = vec![0.0];
+    if pos.x < margin {
+        xs.push(period.x);
+    } else if pos.x > WORLD_W - margin {
+        xs.push(-period.x);
+    }
+    let mut ys = vec![0.0];
+    if pos.y < margin {
+        ys.push(period.y);
+    } else if pos.y > WORLD_H - margin {
+        ys.push(-period.y);
+    }
+    let mut offsets = vec![];
+    for y in ys.iter() {
+        for x in xs.iter() {
+            offsets.push(Vec2::new(*x, *y));
+        }
+    }
+    return offsets;
+}
+
 pub fn make_isometry(posx: f32, posy: f32, rotation: f32) -> Isometry2<f32> {
     let iso = Isometry2::new(Vector2::new(posx, posy), rotation);
     return iso;
@@ -91,7 +132,7 @@ pub fn vec2_to_point2(v: &Vec2) -> Point2<f32> {
     return Point2::new(v.x, v.y);
 }
 
-pub fn vec2_to_point2_collection(vec2_list: &Vec<Vec2>) -> Vec<Point2<f32>> {
+pub fn vec2_to_point2_collection(vec2_list: &[Vec2]) -> Vec<Point2<f32>> {
     let mut points: Vec<Point2<f32>> = vec![];
     for v in vec2_list.iter() {
         let p = Point2::new(v.x, v.y);
@@ -101,7 +142,23 @@ pub fn vec2_to_point2_collection(vec2_list: &Vec<Vec2>) -> Vec<Point2<f32>> {
     return points;
 }
 
-pub fn vec2_to_point2_array(vec2_list: &Vec<Vec2>) -> Matrix<Point2<f32>> {
+/// Convex hull of the points as a shape, or `None` unless it has area: at least three vertices not in a line.
+pub fn convex_hull_shape(vertices: &[Vec2]) -> Option<SharedShape> {
+    // Parry asserts on degenerate input, so make sure the points span some area before asking it.
+    let first = *vertices.first()?;
+    let far = vertices.iter().copied().max_by(|a, b| a.distance_squared(first).total_cmp(&b.distance_squared(first)))?;
+    let spread = far - first;
+    if !vertices.iter().any(|v| spread.perp_dot(*v - first).abs() > 1e-3 * spread.length_squared().max(f32::EPSILON)) {
+        return None;
+    }
+    let shape = SharedShape::convex_hull(&vec2_to_point2_collection(vertices))?;
+    if shape.as_convex_polygon()?.points().len() < 3 {
+        return None;
+    }
+    return Some(shape);
+}
+
+pub fn vec2_to_point2_array(vec2_list: &[Vec2]) -> Matrix<Point2<f32>> {
     let l = vec2_list.len();
     let mut points: Matrix<Point2<f32>>;
     let vecs = vec2_to_point2_collection(vec2_list);
@@ -116,14 +173,27 @@ pub fn contact_mouse(mouse_pos: Vec2, target_pos: Vec2, target_rad: f32) -> bool
     let pos2 = make_isometry(v2.x, v2.y, 0.0);
     let ball1 = Ball::new(2.0);
     let ball2 = Ball::new(target_rad);
-    match contact(&pos1, &ball1, &pos2, &ball2, 0.0).unwrap() {
-        Some(_) => true,
-        None => false,
-    }
+    return contact(&pos1, &ball1, &pos2, &ball2, 0.0).unwrap().is_some();
 }
 
+/// Position and heading of `isometry`; the heading is rapier's angle as is, so local +x is the body's nose.
 pub fn iso_to_vec2_rot(isometry: &Isometry<Real>) -> (Vec2, f32) {
     let pos = Vec2::new(isometry.translation.x, isometry.translation.y);
-    let rot = isometry.rotation.angle() + PI;
+    let rot = isometry.rotation.angle();
     return (pos, rot);
-}
\ No newline at end of file
+}
+
+/// Serde adapter storing a macroquad `Color` as an `[r, g, b, a]` array.
+pub mod serde_color {
+    use macroquad::color::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        return [color.r, color.g, color.b, color.a].serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let [r, g, b, a] = <[f32; 4]>::deserialize(deserializer)?;
+        return Ok(Color::new(r, g, b, a));
+    }
+}